@@ -0,0 +1,131 @@
+/// Analysis done once per piece of code, before it's run: finding the valid jump destinations,
+/// D(c) in section 9.4.3 of the Yellow Paper. A `CodeCache` keeps each analysis by the hash of
+/// the code, so code that runs again, whether it's called twice or run by another VM sharing the
+/// cache, isn't analysed again.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use bigint::uint::U256;
+use opcodes;
+use {JUMPDEST, K256};
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct AnalyzedCode {
+    bytes: Vec<u8>,
+    /// one bit per byte of code, set where that byte is a JUMPDEST instruction (and not part of
+    /// some PUSH's immediate data)
+    jumpdests: Vec<u64>,
+}
+
+impl AnalyzedCode {
+    pub fn new(bytes: Vec<u8>) -> AnalyzedCode {
+        let mut jumpdests = vec![0u64; bytes.len().div_ceil(64)];
+        let mut pc = 0;
+        while pc < bytes.len() {
+            let op = bytes[pc];
+            if op == JUMPDEST {
+                jumpdests[pc / 64] |= 1 << (pc % 64);
+            } else if let Some(info) = opcodes::info(op) {
+                // skip the immediate data
+                pc += info.immediate;
+            }
+            pc += 1;
+        }
+        AnalyzedCode { bytes, jumpdests }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn get(&self, pc: usize) -> Option<u8> {
+        self.bytes.get(pc).cloned()
+    }
+
+    /// Whether `dest` is in D(c), ie whether JUMP and JUMPI may go there
+    pub fn is_valid_jump(&self, dest: U256) -> bool {
+        if dest >= U256::from(self.bytes.len()) {
+            return false;
+        }
+        let dest = dest.low_u64() as usize;
+        self.jumpdests[dest / 64] & (1 << (dest % 64)) != 0
+    }
+}
+
+/// Analysed code by its hash. Clones share one cache, so VMs given clones of it analyse each
+/// piece of code once between them.
+#[derive(Clone, Debug, Default)]
+pub struct CodeCache(Rc<RefCell<HashMap<K256, Rc<AnalyzedCode>>>>);
+
+impl CodeCache {
+    pub fn new() -> CodeCache {
+        CodeCache::default()
+    }
+
+    /// The analysis of `code`, whose hash is `hash`, analysing it only if it isn't cached already
+    pub fn analyze(&self, hash: K256, code: Vec<u8>) -> Rc<AnalyzedCode> {
+        self.0.borrow_mut().entry(hash).or_insert_with(|| Rc::new(AnalyzedCode::new(code))).clone()
+    }
+
+    /// The number of pieces of code analysed
+    pub fn len(&self) -> usize {
+        self.0.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use bigint::uint::U256;
+    use analysis::{AnalyzedCode, CodeCache};
+    use *;
+
+    #[test]
+    fn jumpdests() {
+        let code = AnalyzedCode::new(vec![JUMPDEST, PUSH2, JUMPDEST, JUMPDEST, JUMPDEST, STOP]);
+        assert!(code.is_valid_jump(U256::from(0)));
+        // inside PUSH2's immediate data
+        assert!(!code.is_valid_jump(U256::from(2)));
+        assert!(!code.is_valid_jump(U256::from(3)));
+        assert!(code.is_valid_jump(U256::from(4)));
+        // not a JUMPDEST
+        assert!(!code.is_valid_jump(U256::from(5)));
+        // past the end
+        assert!(!code.is_valid_jump(U256::from(6)));
+        assert!(!code.is_valid_jump(U256::max_value()));
+    }
+
+    #[test]
+    fn truncated_push() {
+        let mut bytes = vec![JUMPDEST; 70];
+        bytes[65] = PUSH32;
+        let code = AnalyzedCode::new(bytes);
+        assert!(code.is_valid_jump(U256::from(64)));
+        assert!(!code.is_valid_jump(U256::from(66)));
+        assert!(!code.is_valid_jump(U256::from(69)));
+    }
+
+    #[test]
+    fn cache() {
+        let cache = CodeCache::new();
+        let code = vec![PUSH1, 0, JUMPDEST];
+        let first = cache.analyze(K256(keccak(&code)), code.clone());
+        // a clone shares the analysis
+        let second = cache.clone().analyze(K256(keccak(&code)), code);
+        assert!(Rc::ptr_eq(&first, &second));
+        assert_eq!(cache.len(), 1);
+        assert!(second.is_valid_jump(U256::from(2)));
+    }
+}
@@ -0,0 +1,339 @@
+/// An assembler for writing EVM programs as text rather than byte vectors:
+///
+/// ```text
+/// ; count down from 3
+///         PUSH 3
+/// loop:   JUMPDEST
+///         PUSH 1 SWAP1 SUB        ; several instructions can share a line
+///         DUP1 PUSH loop JUMPI
+///         STOP
+/// ```
+///
+/// Mnemonics are the ones in the opcode table, in any case. `PUSH` takes a decimal or hex
+/// literal or a label and picks the smallest PUSHn that holds it, while `PUSHn` is exactly that
+/// instruction. `name:` defines a label at the offset of the next instruction, without adding a
+/// JUMPDEST. Comments run from `;` or `//` to the end of the line. Offsets such as `0004:`, bytes
+/// written `UNKNOWN(0x0c)` and a last `PUSHn 0x.. (truncated)`, which is only the bytes given, are
+/// accepted too, so listings from `disasm` assemble back into the same code.
+use std::cmp;
+use std::collections::HashMap;
+use bigint::uint::U256;
+use data_encoding::HEXLOWER;
+use opcodes;
+use {PUSH1, PUSH32};
+
+/// An error in the source, with the line it's on, counting from 1
+#[derive(PartialEq, Clone, Debug)]
+pub enum AsmError {
+    UnknownInstruction(usize, String),
+    /// A PUSH without a value
+    MissingOperand(usize),
+    /// A value that isn't a number or a label
+    InvalidOperand(usize, String),
+    /// A value too big for the PUSH it's given to
+    OperandTooLarge(usize, String),
+    UndefinedLabel(usize, String),
+    DuplicateLabel(usize, String),
+    /// An instruction after a truncated PUSH, which has to end the code
+    TruncatedPush(usize),
+}
+
+/// Assembled code, and the offset of every label in it
+#[derive(PartialEq, Clone, Debug)]
+pub struct Program {
+    pub code: Vec<u8>,
+    pub labels: HashMap<String, usize>,
+}
+
+enum Operand {
+    Literal(U256),
+    Label(String),
+}
+
+enum Item {
+    /// An instruction without immediate data, a byte that isn't an instruction, or the data of a
+    /// truncated PUSH
+    Byte(u8),
+    /// A PUSH of `size` bytes, or the smallest that fits if it isn't given
+    Push { size: Option<usize>, operand: Operand, line: usize },
+    Label(String, usize),
+}
+
+/// The number of bytes needed to hold `value`, at least one
+fn bytes_needed(value: U256) -> usize {
+    cmp::max(value.bits().div_ceil(8), 1)
+}
+
+fn parse_literal(token: &str) -> Option<U256> {
+    let lower = token.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        if hex.is_empty() || hex.len() > 64 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let padded = if hex.len() % 2 == 0 { hex.to_string() } else { format!("0{}", hex) };
+        HEXLOWER.decode(padded.as_bytes()).ok().map(|bytes| U256::from_big_endian(&bytes))
+    } else {
+        U256::from_dec_str(token).ok()
+    }
+}
+
+fn parse_operand(line: usize, token: Option<&str>) -> Result<Operand, AsmError> {
+    let token = token.ok_or(AsmError::MissingOperand(line))?;
+    if token.starts_with(|c: char| c.is_ascii_digit()) {
+        parse_literal(token)
+            .map(Operand::Literal)
+            .ok_or_else(|| AsmError::InvalidOperand(line, token.to_string()))
+    } else if token.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+        Ok(Operand::Label(token.to_string()))
+    } else {
+        Err(AsmError::InvalidOperand(line, token.to_string()))
+    }
+}
+
+/// The bytes of a hex literal, as many as it's written with
+fn parse_hex_bytes(token: &str) -> Option<Vec<u8>> {
+    let lower = token.to_ascii_lowercase();
+    HEXLOWER.decode(lower.strip_prefix("0x")?.as_bytes()).ok()
+}
+
+/// `UNKNOWN(0x..)`, the disassembler's rendering of a byte that isn't an instruction
+fn parse_unknown(token: &str) -> Option<u8> {
+    let hex = token.strip_prefix("UNKNOWN(0x")?.strip_suffix(')')?;
+    u8::from_str_radix(hex, 16).ok()
+}
+
+fn parse(source: &str) -> Result<Vec<Item>, AsmError> {
+    let mut items = Vec::new();
+    // the line of a truncated PUSH, after which there can only be labels
+    let mut truncated = None;
+    for (i, line) in source.lines().enumerate() {
+        let line_number = i + 1;
+        let line = line.split(';').next().unwrap_or("");
+        let line = line.split("//").next().unwrap_or("");
+        let mut tokens = line.split_whitespace().peekable();
+        while let Some(token) = tokens.next() {
+            if let Some(name) = token.strip_suffix(':') {
+                // a leading offset, as in a listing, is only a comment
+                if !name.starts_with(|c: char| c.is_ascii_digit()) {
+                    items.push(Item::Label(name.to_string(), line_number));
+                }
+                continue;
+            }
+            if let Some(line) = truncated {
+                return Err(AsmError::TruncatedPush(line));
+            }
+            if let Some(byte) = parse_unknown(token) {
+                items.push(Item::Byte(byte));
+                continue;
+            }
+
+            let mnemonic = token.to_ascii_uppercase();
+            if mnemonic == "PUSH" {
+                let operand = parse_operand(line_number, tokens.next())?;
+                items.push(Item::Push { size: None, operand, line: line_number });
+                continue;
+            }
+            let op = opcodes::from_mnemonic(&mnemonic)
+                .ok_or_else(|| AsmError::UnknownInstruction(line_number, token.to_string()))?;
+            if (PUSH1..=PUSH32).contains(&op) {
+                let size = usize::from(op - PUSH1 + 1);
+                if tokens.peek() == Some(&"(truncated)") {
+                    // the code ends right after the opcode, without any immediate data
+                    tokens.next();
+                    items.push(Item::Byte(op));
+                    truncated = Some(line_number);
+                    continue;
+                }
+                let operand = tokens.next();
+                if tokens.peek() == Some(&"(truncated)") {
+                    // the code ends part way through the immediate data, so only the bytes there
+                    // are go in
+                    tokens.next();
+                    let operand = operand.ok_or(AsmError::MissingOperand(line_number))?;
+                    let bytes = parse_hex_bytes(operand)
+                        .filter(|bytes| bytes.len() < size)
+                        .ok_or_else(|| AsmError::InvalidOperand(line_number, operand.to_string()))?;
+                    items.push(Item::Byte(op));
+                    items.extend(bytes.into_iter().map(Item::Byte));
+                    truncated = Some(line_number);
+                } else {
+                    let operand = parse_operand(line_number, operand)?;
+                    items.push(Item::Push { size: Some(size), operand, line: line_number });
+                }
+            } else {
+                items.push(Item::Byte(op));
+            }
+        }
+    }
+    Ok(items)
+}
+
+/// Assemble `source` into code
+pub fn assemble(source: &str) -> Result<Program, AsmError> {
+    let items = parse(source)?;
+
+    let mut labels = HashMap::new();
+    for item in &items {
+        if let Item::Label(ref name, line) = *item {
+            if labels.insert(name.clone(), 0).is_some() {
+                return Err(AsmError::DuplicateLabel(line, name.clone()));
+            }
+        }
+    }
+
+    // A label's offset depends on the size of every PUSH before it, including PUSHes of labels.
+    // Start them all at one byte and lay the code out again whenever one has to grow. Sizes only
+    // ever grow, so this settles.
+    let mut sizes: Vec<usize> = items.iter().map(|item| match *item {
+        Item::Push { size: Some(size), .. } => size,
+        Item::Push { operand: Operand::Literal(value), .. } => bytes_needed(value),
+        _ => 1,
+    }).collect();
+    loop {
+        let mut pc = 0;
+        for (item, size) in items.iter().zip(&sizes) {
+            match *item {
+                Item::Label(ref name, _) => { labels.insert(name.clone(), pc); },
+                Item::Byte(_) => pc += 1,
+                Item::Push { .. } => pc += 1 + size,
+            }
+        }
+
+        let mut grew = false;
+        for (item, size) in items.iter().zip(sizes.iter_mut()) {
+            if let Item::Push { size: None, operand: Operand::Label(ref name), .. } = *item {
+                if let Some(&offset) = labels.get(name) {
+                    let needed = bytes_needed(U256::from(offset));
+                    if needed > *size {
+                        *size = needed;
+                        grew = true;
+                    }
+                }
+            }
+        }
+        if !grew {
+            break;
+        }
+    }
+
+    let mut code = Vec::new();
+    for (item, &size) in items.iter().zip(&sizes) {
+        match *item {
+            Item::Label(..) => (),
+            Item::Byte(byte) => code.push(byte),
+            Item::Push { ref operand, line, .. } => {
+                let value = match *operand {
+                    Operand::Literal(value) => value,
+                    Operand::Label(ref name) => labels.get(name)
+                        .map(|&offset| U256::from(offset))
+                        .ok_or_else(|| AsmError::UndefinedLabel(line, name.clone()))?,
+                };
+                if bytes_needed(value) > size {
+                    return Err(AsmError::OperandTooLarge(line, format!("{}", value)));
+                }
+                let mut word = [0u8; 32];
+                value.to_big_endian(&mut word);
+                code.push(PUSH1 + (size - 1) as u8);
+                code.extend_from_slice(&word[32 - size..]);
+            },
+        }
+    }
+
+    Ok(Program { code, labels })
+}
+
+#[cfg(test)]
+mod tests {
+    use asm::{assemble, AsmError};
+    use disasm::{disassemble, labelled_listing, listing};
+    use *;
+
+    #[test]
+    fn assembles() {
+        let program = assemble("
+            ; count down from 3
+                    PUSH 3
+            loop:   JUMPDEST
+                    PUSH 1 swap1 SUB    // mnemonics in any case
+                    DUP1 PUSH loop JUMPI
+                    STOP
+        ").unwrap();
+        assert_eq!(program.code, vec![
+            PUSH1, 3, JUMPDEST, PUSH1, 1, SWAP1, SUB, DUP1, PUSH1, 2, JUMPI, STOP,
+        ]);
+        assert_eq!(program.labels["loop"], 2);
+    }
+
+    #[test]
+    fn literals() {
+        let program = assemble("PUSH 0 PUSH 255 PUSH 256 PUSH 0xABCDEF PUSH4 1 PUSH0").unwrap();
+        assert_eq!(program.code, vec![
+            PUSH1, 0, PUSH1, 255, PUSH2, 1, 0, PUSH3, 0xab, 0xcd, 0xef, PUSH4, 0, 0, 0, 1, PUSH0,
+        ]);
+        let max = format!("PUSH 0x{}", "ff".repeat(32));
+        let mut expected = vec![PUSH32];
+        expected.extend_from_slice(&[0xff; 32]);
+        assert_eq!(assemble(&max).unwrap().code, expected);
+    }
+
+    #[test]
+    fn label_pushes_grow() {
+        // 300 bytes of padding puts the label past what a PUSH1 can hold, which moves it again
+        let source = format!("PUSH end JUMP {} end: JUMPDEST", "STOP ".repeat(300));
+        let program = assemble(&source).unwrap();
+        assert_eq!(program.labels["end"], 304);
+        assert_eq!(&program.code[..4], &[PUSH2, 0x01, 0x30, JUMP]);
+        assert_eq!(program.code[304], JUMPDEST);
+
+        // an explicit size is kept, even when the label doesn't fit
+        let source = format!("PUSH1 end {} end:", "STOP ".repeat(300));
+        assert_eq!(assemble(&source), Err(AsmError::OperandTooLarge(1, "302".to_string())));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(assemble("ADD\nFOO"), Err(AsmError::UnknownInstruction(2, "FOO".to_string())));
+        assert_eq!(assemble("PUSH"), Err(AsmError::MissingOperand(1)));
+        assert_eq!(assemble("PUSH 0xzz"), Err(AsmError::InvalidOperand(1, "0xzz".to_string())));
+        assert_eq!(assemble("PUSH -1"), Err(AsmError::InvalidOperand(1, "-1".to_string())));
+        assert_eq!(assemble("PUSH1 256"), Err(AsmError::OperandTooLarge(1, "256".to_string())));
+        assert_eq!(assemble("PUSH nowhere"), Err(AsmError::UndefinedLabel(1, "nowhere".to_string())));
+        assert_eq!(assemble("a:\na:"), Err(AsmError::DuplicateLabel(2, "a".to_string())));
+        assert_eq!(assemble("PUSH2 0xff (truncated)\nSTOP"), Err(AsmError::TruncatedPush(1)));
+        // a truncated PUSH is short of data, and written in hex
+        assert_eq!(assemble("PUSH2 0xffff (truncated)"), Err(AsmError::InvalidOperand(1, "0xffff".to_string())));
+        assert_eq!(assemble("PUSH2 255 (truncated)"), Err(AsmError::InvalidOperand(1, "255".to_string())));
+    }
+
+    #[test]
+    fn truncated_push() {
+        let program = assemble("PUSH1 1 PUSH3 0xabcd (truncated)\nend:").unwrap();
+        assert_eq!(program.code, vec![PUSH1, 1, PUSH3, 0xab, 0xcd]);
+        assert_eq!(program.labels["end"], 5);
+    }
+
+    #[test]
+    fn round_trips() {
+        let code = vec![
+            PUSH1, 0x80, PUSH1, 0x40, MSTORE, PUSH1, 0x0d, JUMPI, 0x0c, PUSH2, 0x00, 0x5b,
+            STOP, JUMPDEST, PUSH32,
+        ];
+        let mut code = code;
+        code.extend_from_slice(&[0x11; 32]);
+        // the code ends part way through the last PUSH
+        code.extend_from_slice(&[RETURN, PUSH3, 0xaa, 0xbb]);
+
+        let instructions = disassemble(&code);
+        assert_eq!(assemble(&listing(&instructions)).unwrap().code, code);
+        let program = assemble(&labelled_listing(&instructions)).unwrap();
+        assert_eq!(program.code, code);
+        assert_eq!(program.labels["loc_000d"], 0x0d);
+
+        // code ending right after a PUSH opcode
+        for code in &[vec![PUSH1], vec![ADD, PUSH2]] {
+            let instructions = disassemble(code);
+            assert_eq!(&assemble(&listing(&instructions)).unwrap().code, code);
+            assert_eq!(&assemble(&labelled_listing(&instructions)).unwrap().code, code);
+        }
+    }
+}
@@ -0,0 +1,84 @@
+/// The hashes of earlier blocks, which BLOCKHASH reads. The VM only asks for one of the 256
+/// blocks before the current one; where the hashes come from is up to a `BlockHashes` provider,
+/// either one the embedder fills in or, from Prague, the EIP-2935 history contract in the state.
+use std::collections::HashMap;
+use bigint::uint::U256;
+use state::StateBackend;
+use {Address, K256};
+
+/// How many blocks back BLOCKHASH can see
+pub const BLOCKHASH_WINDOW: u64 = 256;
+
+/// The address of the EIP-2935 history contract
+pub const HISTORY_STORAGE_ADDRESS: Address = Address([
+    0x00, 0x00, 0xf9, 0x08, 0x27, 0xf1, 0xc5, 0x3a, 0x10, 0xcb,
+    0x7a, 0x02, 0x33, 0x5b, 0x17, 0x53, 0x20, 0x00, 0x29, 0x35,
+]);
+
+/// How many block hashes the history contract keeps, in a ring buffer of storage slots
+pub const HISTORY_SERVE_WINDOW: u64 = 8191;
+
+pub trait BlockHashes {
+    /// The hash of block `number`, or zero if it isn't known. `world` is the state as it is now.
+    fn block_hash(&self, world: &dyn StateBackend, number: u64) -> K256;
+}
+
+/// Block hashes kept in memory, set by whoever is running the VM
+#[derive(Clone, Debug, Default)]
+pub struct InMemoryBlockHashes {
+    hashes: HashMap<u64, K256>,
+}
+
+impl InMemoryBlockHashes {
+    pub fn new() -> InMemoryBlockHashes {
+        InMemoryBlockHashes::default()
+    }
+
+    pub fn insert(&mut self, number: u64, hash: K256) {
+        self.hashes.insert(number, hash);
+    }
+}
+
+impl BlockHashes for InMemoryBlockHashes {
+    fn block_hash(&self, _world: &dyn StateBackend, number: u64) -> K256 {
+        self.hashes.get(&number).cloned().unwrap_or(K256(U256::zero()))
+    }
+}
+
+/// Block hashes read from the storage of the history contract (EIP-2935), which from Prague
+/// holds the hash of block n in slot n mod HISTORY_SERVE_WINDOW
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HistoryContract;
+
+impl BlockHashes for HistoryContract {
+    fn block_hash(&self, world: &dyn StateBackend, number: u64) -> K256 {
+        K256(world.storage(&HISTORY_STORAGE_ADDRESS, U256::from(number % HISTORY_SERVE_WINDOW)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bigint::uint::U256;
+    use blockhash::{BlockHashes, HistoryContract, InMemoryBlockHashes, HISTORY_STORAGE_ADDRESS};
+    use state::{InMemoryState, StateBackend};
+    use K256;
+
+    #[test]
+    fn in_memory() {
+        let world = InMemoryState::new();
+        let mut hashes = InMemoryBlockHashes::new();
+        hashes.insert(10, K256(U256::from(0xabcd)));
+        assert_eq!(hashes.block_hash(&world, 10), K256(U256::from(0xabcd)));
+        assert_eq!(hashes.block_hash(&world, 11), K256(U256::zero()));
+    }
+
+    #[test]
+    fn history_contract() {
+        let mut world = InMemoryState::new();
+        world.set_storage(&HISTORY_STORAGE_ADDRESS, U256::from(10), U256::from(0xabcd));
+        assert_eq!(HistoryContract.block_hash(&world, 10), K256(U256::from(0xabcd)));
+        // the slots are a ring buffer
+        assert_eq!(HistoryContract.block_hash(&world, 8201), K256(U256::from(0xabcd)));
+        assert_eq!(HistoryContract.block_hash(&world, 11), K256(U256::zero()));
+    }
+}
@@ -0,0 +1,217 @@
+/// Disassembly: splitting bytecode into its instructions and rendering them for people to read.
+/// Decoding follows the interpreter exactly, using the same opcode table, so the immediate data
+/// of a PUSH is never mistaken for an instruction and a JUMPDEST in the listing is a valid jump
+/// destination.
+use std::collections::HashSet;
+use std::fmt;
+use bigint::uint::U256;
+use data_encoding::HEXLOWER;
+use serde_json;
+use opcodes;
+use {JUMP, JUMPDEST, JUMPI};
+
+/// One instruction found in the code
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Instruction {
+    /// The offset of the opcode in the code
+    pub pc: usize,
+    pub opcode: u8,
+    /// The immediate data following the opcode, which is shorter than the opcode calls for if
+    /// the code ends first
+    pub immediate: Vec<u8>,
+}
+
+impl Instruction {
+    /// The instruction's mnemonic, or `None` if the byte isn't an opcode
+    pub fn mnemonic(&self) -> Option<&'static str> {
+        opcodes::info(self.opcode).map(|info| info.mnemonic)
+    }
+
+    /// Whether the code ends part way through the immediate data. The interpreter reads the
+    /// missing bytes as zeros.
+    pub fn is_truncated(&self) -> bool {
+        opcodes::info(self.opcode).is_some_and(|info| self.immediate.len() < info.immediate)
+    }
+
+    /// The value a PUSH puts on the stack
+    pub fn push_value(&self) -> Option<U256> {
+        let size = opcodes::info(self.opcode)?.immediate;
+        if size == 0 {
+            return None;
+        }
+        let mut word = [0u8; 32];
+        word[32 - size..32 - size + self.immediate.len()].copy_from_slice(&self.immediate);
+        Some(U256::from_big_endian(&word))
+    }
+}
+
+/// The mnemonic, then the immediate data in hex. Bytes that aren't opcodes are shown as
+/// `UNKNOWN(0x..)`.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.mnemonic() {
+            Some(mnemonic) => write!(f, "{}", mnemonic)?,
+            None => write!(f, "UNKNOWN(0x{:02x})", self.opcode)?,
+        }
+        if !self.immediate.is_empty() {
+            write!(f, " 0x{}", HEXLOWER.encode(&self.immediate))?;
+        }
+        if self.is_truncated() {
+            write!(f, " (truncated)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Split `code` into instructions, in order
+pub fn disassemble(code: &[u8]) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let mut pc = 0;
+    while pc < code.len() {
+        let opcode = code[pc];
+        let size = opcodes::info(opcode).map_or(0, |info| info.immediate);
+        let end = (pc + 1 + size).min(code.len());
+        instructions.push(Instruction { pc, opcode, immediate: code[pc + 1..end].to_vec() });
+        pc += 1 + size;
+    }
+    instructions
+}
+
+/// A classic listing, one instruction per line after its offset:
+///
+/// ```text
+/// 0000: PUSH1 0x80
+/// 0002: PUSH1 0x40
+/// 0004: MSTORE
+/// ```
+pub fn listing(instructions: &[Instruction]) -> String {
+    let mut out = String::new();
+    for instruction in instructions {
+        out.push_str(&format!("{:04x}: {}\n", instruction.pc, instruction));
+    }
+    out
+}
+
+#[derive(Serialize)]
+struct JsonInstruction {
+    pc: usize,
+    opcode: u8,
+    mnemonic: Option<&'static str>,
+    immediate: Option<String>,
+    truncated: bool,
+}
+
+/// The instructions as a JSON array of objects, with the immediate data as a hex string
+pub fn to_json(instructions: &[Instruction]) -> String {
+    let items: Vec<JsonInstruction> = instructions.iter().map(|instruction| JsonInstruction {
+        pc: instruction.pc,
+        opcode: instruction.opcode,
+        mnemonic: instruction.mnemonic(),
+        immediate: if instruction.immediate.is_empty() {
+            None
+        } else {
+            Some(format!("0x{}", HEXLOWER.encode(&instruction.immediate)))
+        },
+        truncated: instruction.is_truncated(),
+    }).collect();
+    serde_json::to_string_pretty(&items).expect("instructions serialize")
+}
+
+/// A listing with a label on every jump destination, and the PUSH feeding each JUMP or JUMPI
+/// shown as the label it jumps to when it's one of them:
+///
+/// ```text
+/// 0000: PUSH1 loc_0004
+/// 0002: JUMP
+/// loc_0004:
+/// 0004: JUMPDEST
+/// ```
+pub fn labelled_listing(instructions: &[Instruction]) -> String {
+    let jumpdests: HashSet<usize> = instructions.iter()
+        .filter(|instruction| instruction.opcode == JUMPDEST)
+        .map(|instruction| instruction.pc)
+        .collect();
+
+    let mut out = String::new();
+    for (i, instruction) in instructions.iter().enumerate() {
+        if jumpdests.contains(&instruction.pc) {
+            out.push_str(&format!("loc_{:04x}:\n", instruction.pc));
+        }
+        let feeds_jump = instructions.get(i + 1)
+            .is_some_and(|next| next.opcode == JUMP || next.opcode == JUMPI);
+        let target = instruction.push_value()
+            .filter(|value| feeds_jump && *value < U256::from(usize::MAX))
+            .map(|value| value.low_u64() as usize)
+            .filter(|target| jumpdests.contains(target));
+        match (target, instruction.mnemonic()) {
+            (Some(target), Some(mnemonic)) =>
+                out.push_str(&format!("{:04x}: {} loc_{:04x}\n", instruction.pc, mnemonic, target)),
+            _ => out.push_str(&format!("{:04x}: {}\n", instruction.pc, instruction)),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use disasm::{disassemble, labelled_listing, listing, to_json, Instruction};
+    use serde_json::{self, Value};
+    use *;
+
+    #[test]
+    fn decodes() {
+        let code = [PUSH2, 0xab, 0xcd, ADD, 0x0c, PUSH3, 0x01];
+        let instructions = disassemble(&code);
+        assert_eq!(instructions, vec![
+            Instruction { pc: 0, opcode: PUSH2, immediate: vec![0xab, 0xcd] },
+            Instruction { pc: 3, opcode: ADD, immediate: vec![] },
+            Instruction { pc: 4, opcode: 0x0c, immediate: vec![] },
+            Instruction { pc: 5, opcode: PUSH3, immediate: vec![0x01] },
+        ]);
+        assert_eq!(instructions[2].mnemonic(), None);
+        assert!(!instructions[0].is_truncated());
+        assert!(instructions[3].is_truncated());
+        // missing immediate bytes are zeros, like the interpreter reads them
+        assert_eq!(instructions[3].push_value(), Some(U256::from(0x010000)));
+        assert_eq!(instructions[1].push_value(), None);
+        assert!(disassemble(&[]).is_empty());
+    }
+
+    #[test]
+    fn renders() {
+        let instructions = disassemble(&[PUSH1, 0x80, PUSH1, 0x40, MSTORE, 0x0c, PUSH2, 0xff]);
+        assert_eq!(listing(&instructions), "\
+0000: PUSH1 0x80
+0002: PUSH1 0x40
+0004: MSTORE
+0005: UNKNOWN(0x0c)
+0006: PUSH2 0xff (truncated)
+");
+
+        let json: Value = serde_json::from_str(&to_json(&instructions)).unwrap();
+        assert_eq!(json[0]["pc"], 0);
+        assert_eq!(json[0]["mnemonic"], "PUSH1");
+        assert_eq!(json[0]["immediate"], "0x80");
+        assert_eq!(json[2]["immediate"], Value::Null);
+        assert_eq!(json[3]["mnemonic"], Value::Null);
+        assert_eq!(json[3]["opcode"], 12);
+        assert_eq!(json[4]["truncated"], true);
+    }
+
+    #[test]
+    fn labels() {
+        // a jump over a PUSH whose data looks like a JUMPDEST, and a push of a jump destination
+        // that isn't jumped to
+        let code = [PUSH1, 0x06, JUMPI, PUSH1, JUMPDEST, STOP, JUMPDEST, PUSH1, 0x06, POP];
+        assert_eq!(labelled_listing(&disassemble(&code)), "\
+0000: PUSH1 loc_0006
+0002: JUMPI
+0003: PUSH1 0x5b
+0005: STOP
+loc_0006:
+0006: JUMPDEST
+0007: PUSH1 0x06
+0009: POP
+");
+    }
+}
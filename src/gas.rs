@@ -0,0 +1,269 @@
+/// Gas costs from Appendix G (Fee Schedule) of the Yellow Paper, and the per-instruction cost
+/// function C from Appendix H. The constants are the latest values; costs that changed over
+/// the hard forks are functions of the fork.
+use bigint::uint::U256;
+use hardfork::Hardfork;
+use *;
+
+pub const G_ZERO:      u64 = 0;
+pub const G_BASE:      u64 = 2;
+pub const G_VERYLOW:   u64 = 3;
+pub const G_LOW:       u64 = 5;
+pub const G_MID:       u64 = 8;
+pub const G_HIGH:      u64 = 10;
+pub const G_EXTCODE:   u64 = 700;
+pub const G_BALANCE:   u64 = 700;
+pub const G_EXTCODEHASH: u64 = 700;
+pub const G_SLOAD:     u64 = 800;
+pub const G_WARMACCESS: u64 = 100;
+pub const G_COLDACCOUNTACCESS: u64 = 2600;
+pub const G_COLDSLOAD: u64 = 2100;
+pub const G_SSET:      u64 = 20000;
+pub const G_SRESET:    u64 = 5000;
+pub const R_SCLEAR:    i64 = 4800;
+pub const G_CALLSTIPEND: u64 = 2300;
+pub const G_CREATE:    u64 = 32000;
+pub const G_INITCODEWORD: u64 = 2;
+pub const G_CODEDEPOSIT: u64 = 200;
+pub const G_SELFDESTRUCT: u64 = 5000;
+pub const R_SELFDESTRUCT: i64 = 24000;
+pub const G_CALL:      u64 = 700;
+pub const G_CALLVALUE: u64 = 9000;
+pub const G_NEWACCOUNT: u64 = 25000;
+pub const G_LOG:       u64 = 375;
+pub const G_LOGDATA:   u64 = 8;
+pub const G_LOGTOPIC:  u64 = 375;
+pub const G_JUMPDEST:  u64 = 1;
+pub const G_EXP:       u64 = 10;
+pub const G_EXPBYTE:   u64 = 50;
+pub const G_SHA3:      u64 = 30;
+pub const G_SHA3WORD:  u64 = 6;
+pub const G_COPY:      u64 = 3;
+pub const G_BLOCKHASH: u64 = 20;
+pub const G_MEMORY:    u64 = 3;
+pub const G_QUADDIVISOR: u64 = 512;
+
+/// Number of 32-byte words needed to hold `len` bytes, ie ceil(len / 32).
+pub fn words(len: U256) -> U256 {
+    let (q, r) = (len / U256::from(32), len % U256::from(32));
+    if r.is_zero() { q } else { q + U256::one() }
+}
+
+/// C_EXP: G_exp plus G_expbyte for every byte of the exponent. G_expbyte was 10 until Spurious
+/// Dragon (EIP-160).
+pub fn exp_cost(fork: Hardfork, exponent: U256) -> U256 {
+    let bytes = exponent.bits().div_ceil(8);
+    let per_byte = if fork >= Hardfork::SpuriousDragon { G_EXPBYTE } else { 10 };
+    U256::from(G_EXP) + U256::from(per_byte) * U256::from(bytes)
+}
+
+/// Cost of an instruction that does `base` work and then copies `len` bytes (CALLDATACOPY,
+/// CODECOPY, EXTCODECOPY)
+pub fn copy_cost(base: u64, len: U256) -> U256 {
+    U256::from(base).saturating_add(U256::from(G_COPY).saturating_mul(words(len)))
+}
+
+/// C_SHA3: G_sha3 plus G_sha3word for every word hashed
+pub fn sha3_cost(len: U256) -> U256 {
+    U256::from(G_SHA3).saturating_add(U256::from(G_SHA3WORD).saturating_mul(words(len)))
+}
+
+/// The cost of reading another account or a storage slot: raised by Tangerine Whistle (EIP-150)
+/// and Istanbul (EIP-1884), then from Berlin (EIP-2929) the cost of a warm access, with cold
+/// accesses charged extra by `cold_access_cost`.
+fn state_access_cost(op: u8, fork: Hardfork) -> u64 {
+    if fork >= Hardfork::Berlin {
+        return G_WARMACCESS;
+    }
+    match op {
+        SLOAD if fork >= Hardfork::Istanbul => G_SLOAD,
+        SLOAD if fork >= Hardfork::TangerineWhistle => 200,
+        SLOAD => 50,
+        BALANCE if fork >= Hardfork::Istanbul => G_BALANCE,
+        BALANCE if fork >= Hardfork::TangerineWhistle => 400,
+        EXTCODEHASH if fork >= Hardfork::Istanbul => G_EXTCODEHASH,
+        EXTCODEHASH => 400,
+        // EXTCODESIZE, EXTCODECOPY and the calls
+        _ if fork >= Hardfork::TangerineWhistle => G_EXTCODE,
+        CALL | CALLCODE | DELEGATECALL | STATICCALL => 40,
+        _ => 20,
+    }
+}
+
+/// The fixed part of an instruction's cost, from the opcode table except where it depends on the
+/// fork. Instructions in the W_zero, W_base, W_verylow, W_low, W_mid and W_high subsets cost
+/// exactly this; the rest add a dynamic part in `cost`.
+pub fn base_cost(op: u8, fork: Hardfork) -> u64 {
+    match op {
+        SLOAD | BALANCE | EXTCODESIZE | EXTCODECOPY | EXTCODEHASH | CALL | CALLCODE | DELEGATECALL
+            | STATICCALL => state_access_cost(op, fork),
+        _ => opcodes::info(op).map_or(G_ZERO, |info| info.gas),
+    }
+}
+
+/// C(σ, μ, I): the full cost of executing `op` with the given stack under `fork`'s rules,
+/// excluding memory expansion and the extra cost of cold accesses.
+pub fn cost(op: u8, stack: &Stack, fork: Hardfork) -> U256 {
+    match op {
+        EXP => exp_cost(fork, stack[1]),
+        SHA3 => sha3_cost(stack[1]),
+        CALLDATACOPY | CODECOPY | RETURNDATACOPY | MCOPY => copy_cost(G_VERYLOW, stack[2]),
+        EXTCODECOPY => copy_cost(base_cost(op, fork), stack[3]),
+        LOG0 ..= LOG4 => U256::from(base_cost(op, fork))
+            .saturating_add(U256::from(G_LOGDATA).saturating_mul(stack[1])),
+        // CREATE2 hashes the init code, and from Shanghai (EIP-3860) init code is charged by the
+        // word
+        CREATE | CREATE2 => {
+            let per_word = if op == CREATE2 { G_SHA3WORD } else { 0 }
+                + if fork >= Hardfork::Shanghai { G_INITCODEWORD } else { 0 };
+            U256::from(G_CREATE).saturating_add(U256::from(per_word).saturating_mul(words(stack[2])))
+        },
+        // the rest of a call's cost depends on the state and the gas left, so it's charged as the
+        // call is made
+        CALL | CALLCODE if !stack[2].is_zero() => U256::from(base_cost(op, fork) + G_CALLVALUE),
+        _ => U256::from(base_cost(op, fork)),
+    }
+}
+
+/// An account, or a storage slot of the executing account, that an instruction accesses
+pub enum Access {
+    Account(Address),
+    Storage(U256),
+}
+
+/// What `op` is about to access, for EIP-2929. SSTORE isn't included, since it checks the gas
+/// left before charging for a cold access.
+pub fn access(op: u8, stack: &Stack) -> Option<Access> {
+    match op {
+        SLOAD => Some(Access::Storage(stack[0])),
+        BALANCE | EXTCODESIZE | EXTCODECOPY | EXTCODEHASH | SELFDESTRUCT =>
+            Some(Access::Account(u256_to_addr(stack[0]))),
+        CALL | CALLCODE | DELEGATECALL | STATICCALL => Some(Access::Account(u256_to_addr(stack[1]))),
+        _ => None,
+    }
+}
+
+/// The extra cost of `op` accessing something cold, the first access to it in the transaction,
+/// from Berlin (EIP-2929). The warm cost is already part of the base cost, except for
+/// SELFDESTRUCT, which pays the whole cold cost.
+pub fn cold_access_cost(op: u8) -> u64 {
+    match op {
+        SLOAD => G_COLDSLOAD - G_WARMACCESS,
+        SELFDESTRUCT => G_COLDACCOUNTACCESS,
+        _ => G_COLDACCOUNTACCESS - G_WARMACCESS,
+    }
+}
+
+/// The cost of depositing `len` bytes of code returned by init code
+pub fn code_deposit_cost(len: usize) -> U256 {
+    U256::from(G_CODEDEPOSIT).saturating_mul(U256::from(len))
+}
+
+/// The cost of SELFDESTRUCT, free before Tangerine Whistle (EIP-150). `creates_account` is
+/// whether it brings the beneficiary into being, which costs G_newaccount on top.
+pub fn selfdestruct_cost(fork: Hardfork, creates_account: bool) -> u64 {
+    if fork < Hardfork::TangerineWhistle {
+        G_ZERO
+    } else if creates_account {
+        G_SELFDESTRUCT + G_NEWACCOUNT
+    } else {
+        G_SELFDESTRUCT
+    }
+}
+
+/// The refund for an account's first SELFDESTRUCT in a transaction, removed in London (EIP-3529)
+pub fn selfdestruct_refund(fork: Hardfork) -> i64 {
+    if fork >= Hardfork::London { 0 } else { R_SELFDESTRUCT }
+}
+
+/// L from EIP-150: all but one 64th of `gas`, the most a call can pass on to its callee
+pub fn all_but_one_64th(gas: U256) -> U256 {
+    gas - gas / U256::from(64)
+}
+
+/// The region of memory `op` reads or writes, as `(offset, length)`, if it touches memory at all.
+/// This is what drives μ'_i, the memory expansion, in Appendix H.
+pub fn memory_range(op: u8, stack: &Stack) -> Option<(U256, U256)> {
+    match op {
+        MLOAD | MSTORE => Some((stack[0], U256::from(32))),
+        MSTORE8 => Some((stack[0], U256::one())),
+        SHA3 => Some((stack[0], stack[1])),
+        CALLDATACOPY | CODECOPY | RETURNDATACOPY => Some((stack[0], stack[2])),
+        // MCOPY reads and writes, so memory has to cover whichever region ends later
+        MCOPY => Some((cmp::max(stack[0], stack[1]), stack[2])),
+        EXTCODECOPY => Some((stack[1], stack[3])),
+        LOG0 ..= LOG4 => Some((stack[0], stack[1])),
+        CREATE | CREATE2 => Some((stack[1], stack[2])),
+        RETURN | REVERT => Some((stack[0], stack[1])),
+        _ => None,
+    }
+}
+
+/// Whether SSTORE is priced by net gas metering, which charges for a write by how it changes the
+/// slot from its value at the start of the transaction: from Constantinople (EIP-1283), taken out
+/// again by Petersburg and back for good in Istanbul (EIP-2200).
+pub fn net_gas_metering(fork: Hardfork) -> bool {
+    fork == Hardfork::Constantinople || fork >= Hardfork::Istanbul
+}
+
+/// R_sclear, the refund for clearing a storage slot, cut by London (EIP-3529)
+pub fn sclear_refund(fork: Hardfork) -> i64 {
+    if fork >= Hardfork::London { R_SCLEAR } else { 15000 }
+}
+
+/// The cost of SSTORE, and the change it makes to the refund counter. `original` is the slot's
+/// value at the start of the transaction, `current` its value now and `new` the value being
+/// stored. SSTORE's base cost is zero so all of it comes from here, except for the extra cost of
+/// a cold slot from Berlin.
+pub fn sstore_cost(fork: Hardfork, original: U256, current: U256, new: U256) -> (u64, i64) {
+    let r_sclear = sclear_refund(fork);
+    if !net_gas_metering(fork) {
+        let cost = if current.is_zero() && !new.is_zero() { G_SSET } else { G_SRESET };
+        let refund = if !current.is_zero() && new.is_zero() { r_sclear } else { 0 };
+        return (cost, refund);
+    }
+
+    // the cost of a write that doesn't change the slot's value from the start of the transaction
+    // is the cost of reading it, and from Berlin (EIP-2929) the cost of resetting a slot leaves
+    // out the cost of a cold read
+    let (sload, sreset) = if fork >= Hardfork::Berlin {
+        (G_WARMACCESS, G_SRESET - G_COLDSLOAD)
+    } else if fork >= Hardfork::Istanbul {
+        (G_SLOAD, G_SRESET)
+    } else {
+        (200, G_SRESET)
+    };
+
+    if current == new {
+        return (sload, 0);
+    }
+
+    if original == current {
+        if original.is_zero() {
+            return (G_SSET, 0);
+        }
+        let refund = if new.is_zero() { r_sclear } else { 0 };
+        return (sreset, refund);
+    }
+
+    // the slot is already dirty
+    let mut refund = 0;
+    if !original.is_zero() {
+        if current.is_zero() {
+            refund -= r_sclear;
+        } else if new.is_zero() {
+            refund += r_sclear;
+        }
+    }
+    if original == new {
+        let reset_to = if original.is_zero() { G_SSET } else { sreset };
+        refund += (reset_to - sload) as i64;
+    }
+    (sload, refund)
+}
+
+/// The most of the refund counter a transaction that used `gas_used` gets back: half of it, and
+/// a fifth from London (EIP-3529)
+pub fn max_refund(fork: Hardfork, gas_used: U256) -> U256 {
+    gas_used / U256::from(if fork >= Hardfork::London { 5 } else { 2 })
+}
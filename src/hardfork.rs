@@ -0,0 +1,30 @@
+use opcodes;
+
+/// The Ethereum mainnet hard forks. Each one changes the rules of execution from its activation
+/// block onwards; they're declared in order, so a later fork compares greater than an earlier one
+/// and `fork >= Hardfork::London` asks whether London's rules apply.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug)]
+pub enum Hardfork {
+    Frontier,
+    Homestead,
+    TangerineWhistle,
+    SpuriousDragon,
+    Byzantium,
+    Constantinople,
+    Petersburg,
+    Istanbul,
+    Berlin,
+    London,
+    Paris,
+    Shanghai,
+    Cancun,
+    Prague,
+}
+
+impl Hardfork {
+    /// Whether `op` is an instruction under this fork's rules. Opcodes from later forks are
+    /// undefined, and halt exceptionally just like any other undefined opcode.
+    pub fn has_opcode(self, op: u8) -> bool {
+        opcodes::info(op).is_some_and(|info| self >= info.fork)
+    }
+}
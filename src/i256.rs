@@ -0,0 +1,266 @@
+/// Signed 256-bit integers. The EVM has no separate signed type: SDIV, SMOD, SLT, SGT and SAR
+/// read their operands as two's complement words. `I256` wraps a word to give it those semantics,
+/// so the signed instructions (and anything analysing them) share one definition.
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Rem, Sub};
+use bigint::uint::U256;
+
+/// A two's complement signed word. Arithmetic wraps like the EVM's, and division and remainder
+/// follow SDIV and SMOD: dividing by zero gives zero, and MIN / -1 overflows back to MIN.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, Default)]
+pub struct I256(U256);
+
+impl I256 {
+    pub fn zero() -> I256 {
+        I256(U256::zero())
+    }
+
+    pub fn one() -> I256 {
+        I256(U256::one())
+    }
+
+    pub fn minus_one() -> I256 {
+        I256(!U256::zero())
+    }
+
+    /// -2^255
+    pub fn min_value() -> I256 {
+        I256(U256::one() << 255)
+    }
+
+    /// 2^255 - 1
+    pub fn max_value() -> I256 {
+        I256(!U256::zero() >> 1)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.0.bit(255)
+    }
+
+    /// The magnitude, which for MIN is 2^255 and so only fits unsigned
+    pub fn abs(&self) -> U256 {
+        if self.is_negative() { (-*self).0 } else { self.0 }
+    }
+
+    /// The negation of a non-negative magnitude, wrapping like everything else
+    fn from_magnitude(magnitude: U256, negative: bool) -> I256 {
+        let value = I256(magnitude);
+        if negative { -value } else { value }
+    }
+
+    /// Arithmetic shift right, filling with the sign bit: negative numbers round towards
+    /// negative infinity, down to -1
+    pub fn sar(&self, shift: U256) -> I256 {
+        let fill = if self.is_negative() { !U256::zero() } else { U256::zero() };
+        if shift >= U256::from(256) {
+            return I256(fill);
+        }
+        let n = shift.low_u64() as usize;
+        if n == 0 {
+            return *self;
+        }
+        I256((self.0 >> n) | (fill << (256 - n)))
+    }
+}
+
+impl From<U256> for I256 {
+    fn from(word: U256) -> I256 {
+        I256(word)
+    }
+}
+
+impl From<I256> for U256 {
+    fn from(value: I256) -> U256 {
+        value.0
+    }
+}
+
+impl From<i64> for I256 {
+    fn from(n: i64) -> I256 {
+        I256::from_magnitude(U256::from(n.unsigned_abs()), n < 0)
+    }
+}
+
+impl Neg for I256 {
+    type Output = I256;
+
+    fn neg(self) -> I256 {
+        I256((!self.0).overflowing_add(U256::one()).0)
+    }
+}
+
+impl Add for I256 {
+    type Output = I256;
+
+    fn add(self, other: I256) -> I256 {
+        I256(self.0.overflowing_add(other.0).0)
+    }
+}
+
+impl Sub for I256 {
+    type Output = I256;
+
+    fn sub(self, other: I256) -> I256 {
+        I256(self.0.overflowing_sub(other.0).0)
+    }
+}
+
+// the low 256 bits of a product are the same whether the operands are read as signed or not
+impl Mul for I256 {
+    type Output = I256;
+
+    fn mul(self, other: I256) -> I256 {
+        I256(self.0.overflowing_mul(other.0).0)
+    }
+}
+
+/// SDIV: rounds towards zero
+impl Div for I256 {
+    type Output = I256;
+
+    fn div(self, other: I256) -> I256 {
+        if other.is_zero() {
+            return I256::zero();
+        }
+        // MIN / -1 is 2^255, which negates back to MIN
+        I256::from_magnitude(self.abs() / other.abs(), self.is_negative() != other.is_negative())
+    }
+}
+
+/// SMOD: the result takes the sign of the dividend
+impl Rem for I256 {
+    type Output = I256;
+
+    fn rem(self, other: I256) -> I256 {
+        if other.is_zero() {
+            return I256::zero();
+        }
+        I256::from_magnitude(self.abs() % other.abs(), self.is_negative())
+    }
+}
+
+impl Ord for I256 {
+    fn cmp(&self, other: &I256) -> Ordering {
+        // flipping the sign bit maps MIN..=MAX onto 0..=2^256-1 in order
+        let bias = U256::one() << 255;
+        (self.0 ^ bias).cmp(&(other.0 ^ bias))
+    }
+}
+
+impl PartialOrd for I256 {
+    fn partial_cmp(&self, other: &I256) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for I256 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_negative() {
+            write!(f, "-{}", self.abs())
+        } else {
+            write!(f, "{}", self.0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bigint::uint::U256;
+    use num::bigint::{BigInt, BigUint, Sign};
+    use num::{Signed, Zero};
+    use i256::I256;
+
+    fn to_big(value: I256) -> BigInt {
+        let mut bytes = [0; 32];
+        value.abs().to_big_endian(&mut bytes);
+        let sign = if value.is_negative() { Sign::Minus } else { Sign::Plus };
+        BigInt::from_biguint(sign, BigUint::from_bytes_be(&bytes))
+    }
+
+    /// `big` reduced modulo 2^256 and read as two's complement
+    fn from_big(big: BigInt) -> I256 {
+        let modulus = BigInt::from_biguint(Sign::Plus, BigUint::from_bytes_be(&[1; 1]) << 256);
+        let wrapped = ((big % &modulus) + &modulus) % &modulus;
+        let (_, bytes) = wrapped.to_bytes_be();
+        I256::from(U256::from_big_endian(&bytes))
+    }
+
+    fn edge_cases() -> Vec<I256> {
+        let mut values = vec![
+            I256::zero(), I256::one(), I256::minus_one(), I256::from(2), I256::from(-2),
+            I256::from(3), I256::from(-3), I256::from(i64::MAX), I256::from(i64::MIN),
+            I256::min_value(), I256::max_value(),
+        ];
+        values.push(I256::min_value() + I256::one());
+        values.push(I256::max_value() - I256::one());
+        values.push(I256::from(U256::one() << 128));
+        values.push(-I256::from(U256::one() << 128));
+        values
+    }
+
+    #[test]
+    fn constants() {
+        assert_eq!(I256::from(-1), I256::minus_one());
+        assert_eq!(I256::max_value() + I256::one(), I256::min_value());
+        assert_eq!(-I256::min_value(), I256::min_value());
+        assert_eq!(I256::min_value().abs(), U256::one() << 255);
+        assert_eq!(I256::from(i64::MIN).abs(), U256::from(1u64 << 63));
+        assert_eq!(U256::from(I256::from(-2)), !U256::zero() - U256::one());
+        assert_eq!(I256::from(-42).to_string(), "-42");
+        assert_eq!(I256::from(42).to_string(), "42");
+    }
+
+    #[test]
+    fn division_edges() {
+        assert_eq!(I256::min_value() / I256::minus_one(), I256::min_value());
+        assert_eq!(I256::min_value() % I256::minus_one(), I256::zero());
+        assert_eq!(I256::from(7) / I256::zero(), I256::zero());
+        assert_eq!(I256::from(-7) % I256::zero(), I256::zero());
+        assert_eq!(I256::from(-7) / I256::from(2), I256::from(-3));
+        assert_eq!(I256::from(-7) % I256::from(2), I256::from(-1));
+        assert_eq!(I256::from(7) % I256::from(-2), I256::one());
+    }
+
+    #[test]
+    fn sar() {
+        let x = I256::from(-8);
+        assert_eq!(x.sar(U256::one()), I256::from(-4));
+        assert_eq!(I256::from(-7).sar(U256::one()), I256::from(-4));
+        assert_eq!(x.sar(U256::from(255)), I256::minus_one());
+        assert_eq!(x.sar(U256::from(1000)), I256::minus_one());
+        assert_eq!(I256::max_value().sar(U256::from(1000)), I256::zero());
+        assert_eq!(I256::max_value().sar(U256::from(254)), I256::one());
+        assert_eq!(x.sar(U256::zero()), x);
+    }
+
+    /// Every operation on every pair of edge cases agrees with arbitrary precision arithmetic
+    /// wrapped to 256 bits
+    #[test]
+    fn matches_bigint() {
+        for &a in &edge_cases() {
+            assert_eq!(from_big(to_big(a)), a);
+            assert_eq!(-a, from_big(-to_big(a)));
+            for &b in &edge_cases() {
+                let (x, y) = (to_big(a), to_big(b));
+                assert_eq!(a + b, from_big(&x + &y), "{} + {}", a, b);
+                assert_eq!(a - b, from_big(&x - &y), "{} - {}", a, b);
+                assert_eq!(a * b, from_big(&x * &y), "{} * {}", a, b);
+                assert_eq!(a.cmp(&b), x.cmp(&y), "{} <=> {}", a, b);
+                if y.is_zero() {
+                    assert_eq!(a / b, I256::zero());
+                    assert_eq!(a % b, I256::zero());
+                } else {
+                    // BigInt rounds towards zero and takes the dividend's sign, like SDIV and SMOD
+                    assert_eq!(a / b, from_big(&x / &y), "{} / {}", a, b);
+                    assert_eq!(a % b, from_big(&x % &y), "{} % {}", a, b);
+                    assert!((&x % &y).is_zero() || (&x % &y).signum() == x.signum());
+                }
+            }
+        }
+    }
+}
@@ -7,6 +7,14 @@ pub mod json {
     use Stack;
     use FrameState;
     use VM;
+    use memory::Memory;
+    use analysis::{AnalyzedCode, CodeCache};
+    use std::rc::Rc;
+    use state::{InMemoryState, Journal};
+    use hardfork::Hardfork;
+    use precompiles::Precompiles;
+    use blockhash::InMemoryBlockHashes;
+    use Substate;
     use Env;
     use Header;
     use Block;
@@ -118,13 +126,15 @@ pub mod json {
     fn init_vm(test: &Test) -> VM {
         VM {
             result: None,
+            output: Vec::new(),
+            frames: Vec::new(),
             state: FrameState {
-                code: hexstr_to_vec(&test.exec.code),
+                code: Rc::new(AnalyzedCode::new(hexstr_to_vec(&test.exec.code))),
                 gas_available: hexstr_to_u256(&test.exec.gas),
                 pc: 0,
-                memory: Vec::new(),
-                active_words: U256::zero(),
+                memory: Memory::new(),
                 stack: Stack::new(),
+                return_data: Vec::new(),
             },
             env: Env {
                 owner: hexstr_to_address(&test.exec.address),
@@ -136,6 +146,8 @@ pub mod json {
                 code: hexstr_to_vec(&test.exec.code),
                 header: Header {},
                 depth: 0,
+                is_static: false,
+                blob_hashes: Vec::new(),
             },
             //XXX - This can be sorted out once the BigUint vs U256 thing is figured out
             block: Block {
@@ -144,7 +156,17 @@ pub mod json {
                 number: U256::one(),
                 gas_limit: U256::one(),
                 timestamp: U256::one(),
-            }
+                base_fee: U256::zero(),
+                chain_id: U256::one(),
+                blob_base_fee: U256::one(),
+            },
+            world: Box::new(InMemoryState::new()),
+            journal: Journal::new(),
+            substate: Substate::default(),
+            fork: Hardfork::Istanbul,
+            precompiles: Precompiles::for_fork(Hardfork::Istanbul),
+            block_hashes: Box::new(InMemoryBlockHashes::new()),
+            code_cache: CodeCache::new(),
         }
     }
 
@@ -152,13 +174,15 @@ pub mod json {
     fn final_vm(test: &Test) -> VM {
         VM {
             result: None,
+            output: Vec::new(),
+            frames: Vec::new(),
             state: FrameState {
-                code: hexstr_to_vec(&test.exec.code),
+                code: Rc::new(AnalyzedCode::new(hexstr_to_vec(&test.exec.code))),
                 gas_available: hexstr_to_u256(&test.gas),
                 pc: 0, //XXX this is not tracked in the json tests so we should just default to the end PC
-                memory: Vec::new(), //XXX
-                active_words: U256::zero(), //XXX
+                memory: Memory::new(), //XXX
                 stack: Stack::new(), //XXX again, not tracked in the json
+                return_data: Vec::new(),
             },
             env: Env {
                 owner: hexstr_to_address(&test.exec.address),
@@ -170,6 +194,8 @@ pub mod json {
                 code: hexstr_to_vec(&test.exec.code),
                 header: Header {},
                 depth: 0,
+                is_static: false,
+                blob_hashes: Vec::new(),
             },
             //XXX - This can be sorted out once the BigUint vs U256 thing is figured out
             block: Block {
@@ -178,7 +204,17 @@ pub mod json {
                 number: U256::one(), 
                 gas_limit: U256::one(),
                 timestamp: U256::one(),
-            }
+                base_fee: U256::zero(),
+                chain_id: U256::one(),
+                blob_base_fee: U256::one(),
+            },
+            world: Box::new(InMemoryState::new()),
+            journal: Journal::new(),
+            substate: Substate::default(),
+            fork: Hardfork::Istanbul,
+            precompiles: Precompiles::for_fork(Hardfork::Istanbul),
+            block_hashes: Box::new(InMemoryBlockHashes::new()),
+            code_cache: CodeCache::new(),
         }
     }
 }
@@ -9,73 +9,114 @@ extern crate nibble_vec;
 extern crate num;
 extern crate sha3;
 extern crate data_encoding;
+extern crate sha2;
+extern crate ripemd160;
+extern crate libsecp256k1;
+extern crate substrate_bn;
+extern crate blst;
+extern crate c_kzg;
+#[cfg(test)]
+extern crate proptest;
 
 pub mod trie;
 pub mod json;
+pub mod gas;
+pub mod memory;
+pub mod analysis;
+pub mod state;
+pub mod hardfork;
+pub mod precompiles;
+pub mod blockhash;
+pub mod wide;
+pub mod i256;
+pub mod opcodes;
+pub mod disasm;
+pub mod asm;
 
 use core::clone::Clone;
-use core::ops::{Add,BitAnd,BitOr,BitXor,Index,IndexMut,Mul,Not,Sub};
-use std::cmp::max;
+use core::ops::{BitAnd,BitOr,BitXor,Index,IndexMut,Not};
 use bigint::uint::U256;
 use num::BigUint;
 use std::convert::From;
+use memory::Memory;
+use analysis::{AnalyzedCode, CodeCache};
+use state::{StateBackend, Journal, create_address, create2_address};
+use hardfork::Hardfork;
+use i256::I256;
+use precompiles::Precompiles;
+use blockhash::{BlockHashes, InMemoryBlockHashes, BLOCKHASH_WINDOW};
+use std::collections::HashSet;
+use std::rc::Rc;
+use std::{cmp, mem};
+use sha3::{Digest, Keccak256};
 
-const homestead: u32 = 1150000;
 
 type Instruction = u8;
 
-#[derive(PartialEq, Clone)]
-pub struct K256(U256);
-
-#[derive(PartialEq, Clone)]
-pub struct Address([u8; 20]);
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub struct K256(pub U256);
+
+#[derive(PartialEq, Eq, Hash, Clone, Debug, Default)]
+pub struct Address(pub [u8; 20]);
+
+/// The reasons execution can halt exceptionally (the Z function in section 9.4.2 of the Yellow
+/// Paper). An exceptional halt consumes all remaining gas and reverts any state changes.
+#[derive(PartialEq, Clone, Debug)]
+pub enum VmError {
+    StackUnderflow,
+    StackOverflow,
+    InvalidJump,
+    InvalidOpcode(u8),
+    OutOfGas,
+    WriteProtection,
+    CallDepthExceeded,
+    /// RETURNDATACOPY reading past the end of the return data (EIP-211)
+    ReturnDataOutOfBounds,
+}
 
-#[derive(PartialEq, Clone)]
+#[derive(PartialEq, Clone, Debug)]
 pub enum VMResult {
-    VmFailure,
+    VmFailure(VmError),
     VmSuccess,
+    /// Halted by REVERT: state changes are undone but the remaining gas isn't consumed
+    VmRevert,
 }
 
-#[derive(Clone)]
-pub struct S256(U256);
+/// The maximum number of items on the stack
+pub const STACK_LIMIT: usize = 1024;
 
+/// The maximum depth of nested message calls and contract creations
+pub const CALL_DEPTH_LIMIT: u16 = 1024;
 
-// Basic version of signed semantics. Kind of gross because
-// most two's complement arithmetic is the same for signed/unsigned
-// so seems unnecessary to implement every op for S256. But on the other
-// hand also annoying to keep wrapping/unwrapping.
-impl S256 {
+/// The largest code a contract creation can deploy (EIP-170)
+pub const MAX_CODE_SIZE: usize = 0x6000;
 
-    fn to_u256(&self) -> U256 {
-        self.0
-    }
+#[derive(PartialEq, Clone)]
+pub struct Stack(Vec<U256>);
 
-    fn abs(&self) -> U256 {
-        let num = self.0;
-        if num.bit(255) {
-            !num + U256::one()
-        } else {
-            num
-        }
+impl Stack {
+    fn new() -> Stack {
+        return Stack(Vec::new());
     }
 
-    fn invert(&self) -> S256 {
-        let num = self.0;
-        S256(!num + U256::one())
+    pub fn len(&self) -> usize {
+        self.0.len()
     }
 
-    fn sign(&self) -> bool {
-        self.0.bit(255)
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
     }
 
-}
-
-#[derive(PartialEq, Clone)]
-pub struct Stack(Vec<U256>);
-
-impl Stack {
-    fn new() -> Stack {
-        return Stack(Vec::new());
+    /// Check that an instruction taking `inputs` items and leaving `outputs` items can run
+    /// without underflowing or overflowing the stack.
+    fn check(&self, inputs: usize, outputs: usize) -> Result<(), VmError> {
+        if self.0.len() < inputs {
+            Err(VmError::StackUnderflow)
+        } else if self.0.len() - inputs + outputs > STACK_LIMIT {
+            Err(VmError::StackOverflow)
+        } else {
+            Ok(())
+        }
     }
 
     fn push(&mut self, value: U256) {
@@ -132,56 +173,77 @@ impl IndexMut<usize> for Stack {
 #[derive(PartialEq, Clone)]
 pub struct FrameState {
     // contract
-    code:          Vec<u8>, // XXX is code part of FrameState or Env?
+    code:          Rc<AnalyzedCode>, // XXX is code part of FrameState or Env?
     gas_available: U256,
     pc:            usize, // U256,
-    memory:        Vec<u8>,
-    active_words:  U256,
+    memory:        Memory,
     stack:         Stack,
-    //     memorySize
+    /// the output of the last call or contract creation this frame made (EIP-211)
+    return_data:   Vec<u8>,
     //     calldata
     //     callvalue
     //     caller
 }
 
 impl FrameState {
-    pub fn m_store(&mut self, loc: usize, word: U256) {
-        let mut bytes: [u8; 32] = [0; 32];
-        word.to_big_endian(&mut bytes);
-
-        for _i in self.memory.len() .. loc + 32 {
-            self.memory.push(0);
-        }
-
-        for (i, byte) in bytes.iter().enumerate() {
-            self.memory[loc + i] = *byte;
+    pub fn new(code: Rc<AnalyzedCode>, gas_available: U256) -> FrameState {
+        FrameState {
+            code,
+            gas_available,
+            pc:            0,
+            memory:        Memory::new(),
+            stack:         Stack::new(),
+            return_data:   Vec::new(),
         }
-        self.active_words = self.active_words.max(U256::from((loc + 32) / 32));
     }
 
-    pub fn m_store8(&mut self, loc: usize, byte: u8) {
-        for _i in self.memory.len() .. loc + 1 {
-            self.memory.push(0);
+    pub fn use_gas(&mut self, amount: U256) -> Result<(), VmError> {
+        if amount > self.gas_available {
+            return Err(VmError::OutOfGas);
         }
+        self.gas_available = self.gas_available - amount;
+        Ok(())
+    }
 
-        self.memory[loc] = byte;
-        self.active_words = self.active_words.max(U256::from((loc + 1) / 32));
+    /// Pay for and perform the memory expansion needed to touch `[offset, offset + len)`
+    pub fn expand_memory(&mut self, offset: U256, len: U256) -> Result<(), VmError> {
+        let cost = self.memory.expansion_cost(offset, len).ok_or(VmError::OutOfGas)?;
+        self.use_gas(cost)?;
+        self.memory.expand(offset, len);
+        Ok(())
     }
+}
 
-    pub fn m_load(&mut self, loc: usize) -> U256 {
-        if self.memory.len() < loc + 32 {
-            self.memory.resize(loc + 32, 0);
-        }
+/// A, the accrued substate (section 6.1 of the Yellow Paper): information gathered during
+/// execution that's acted on once the transaction completes
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct Substate {
+    /// A_r, the refund counter. EIP-2200 can take it down as well as up within a transaction, so
+    /// it's signed, but it never ends a transaction below zero.
+    refund: i64,
 
-        self.active_words = self.active_words.max(U256::from((loc + 32) / 32));
-        return U256::from_big_endian(&self.memory[loc..loc+32]);
-    }
+    /// A_l, the logs emitted so far
+    logs: Vec<Log>,
+
+    /// A_s, the self-destructed accounts, which are deleted when the transaction completes
+    selfdestructs: HashSet<Address>,
+
+    /// The accounts created so far. From Cancun (EIP-6780) SELFDESTRUCT only deletes these.
+    created: HashSet<Address>,
+
+    /// A_a, the accounts accessed so far. From Berlin (EIP-2929) the first access to an account
+    /// costs more than the rest.
+    accessed_addresses: HashSet<Address>,
+
+    /// A_K, the storage slots accessed so far, by the account they belong to
+    accessed_storage_keys: HashSet<(Address, U256)>,
 }
 
-#[derive(PartialEq, Clone)]
+/// σ[a], section 4.1 of the Yellow Paper
+#[derive(PartialEq, Clone, Debug)]
 pub struct AccountState {
-    nonce: u32,
-    balance: u32,
+    nonce: u64,
+    balance: U256,
     storage_root: K256,
     code_hash: K256,
 }
@@ -204,7 +266,66 @@ pub enum Transaction {
     CallTransaction { common: TransactionCommon, data: Vec<u8> },
 }
 
-struct Bloom([u8; 256]);
+/// A 2048-bit Bloom filter over log addresses and topics (section 4.3.1 of the Yellow Paper)
+pub struct Bloom(pub [u8; 256]);
+
+impl Bloom {
+    pub fn new() -> Bloom {
+        Bloom([0; 256])
+    }
+
+    /// The three bits M3:2048(x) sets: the low 11 bits of each of the first three pairs of bytes
+    /// of KEC(x), counting from the least significant bit of the filter
+    fn bits(bytes: &[u8]) -> [usize; 3] {
+        let mut hash = [0u8; 32];
+        keccak(bytes).to_big_endian(&mut hash);
+        let mut bits = [0; 3];
+        for (i, bit) in bits.iter_mut().enumerate() {
+            *bit = ((usize::from(hash[2 * i]) << 8) | usize::from(hash[2 * i + 1])) & 2047;
+        }
+        bits
+    }
+
+    pub fn accrue(&mut self, bytes: &[u8]) {
+        for bit in Bloom::bits(bytes).iter() {
+            self.0[255 - bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    /// Whether `bytes` might have been added. False positives are possible, false negatives
+    /// aren't.
+    pub fn contains(&self, bytes: &[u8]) -> bool {
+        Bloom::bits(bytes).iter().all(|bit| self.0[255 - bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    /// Add a log's address and each of its topics
+    pub fn accrue_log(&mut self, log: &Log) {
+        self.accrue(&log.address.0);
+        for topic in log.topics.iter() {
+            let mut bytes = [0u8; 32];
+            topic.0.to_big_endian(&mut bytes);
+            self.accrue(&bytes);
+        }
+    }
+
+    pub fn from_logs(logs: &[Log]) -> Bloom {
+        let mut bloom = Bloom::new();
+        for log in logs {
+            bloom.accrue_log(log);
+        }
+        bloom
+    }
+}
+
+impl Default for Bloom {
+    fn default() -> Bloom { Bloom::new() }
+}
+
+impl std::fmt::Debug for Bloom {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Bloom({})", data_encoding::HEXLOWER.encode(&self.0))
+    }
+}
 
 impl PartialEq for Bloom {
     fn eq(&self, &Bloom(other): &Bloom) -> bool {
@@ -218,44 +339,57 @@ impl Clone for Bloom {
 }
 
 // for now, filter down to only the fields we actually use
-#[derive(PartialEq, Clone)]
+#[derive(PartialEq, Clone, Default)]
 pub struct Block {
     // parent_hash: K256,
     // ommers_hash: K256,
-    beneficiary: Address,
+    pub beneficiary: Address,
     // state_root: K256,
     // transactions_root: K256,
     // receipts_root: K256,
     // logs_bloom: Bloom,
-    difficulty: U256,
-    number: U256,
-    gas_limit: U256,
+    pub difficulty: U256,
+    pub number: U256,
+    pub gas_limit: U256,
     // gas_used: U256,
-    timestamp: U256,
+    pub timestamp: U256,
+    /// The base fee per gas from London (EIP-1559)
+    pub base_fee: U256,
+    /// The chain the block belongs to, for CHAINID (EIP-1344)
+    pub chain_id: U256,
+    /// The price of blob gas from Cancun (EIP-4844)
+    pub blob_base_fee: U256,
     // extra_data: Vec<u8>,
     // mix_hash: K256,
     // nonce: u64,
 }
 
-#[derive(PartialEq, Clone)]
+#[derive(PartialEq, Clone, Debug)]
 pub struct Log {
-    address: Address,
-    topics: Vec<K256>,
-    data: Vec<u8>,
-    block_number: u64,
-    tx_hash: K256,
-    tx_index: u32,
-    block_hash: K256,
-    index: u32,
-    removed: bool,
+    pub address: Address,
+    pub topics: Vec<K256>,
+    pub data: Vec<u8>,
+    pub block_number: u64,
+    pub tx_hash: K256,
+    pub tx_index: u32,
+    pub block_hash: K256,
+    pub index: u32,
+    pub removed: bool,
 }
 
-#[derive(PartialEq, Clone)]
+#[derive(PartialEq, Clone, Debug)]
 pub struct TransactionReceipt {
     // state:
-    gas_used: U256,
-    logs: Vec<Log>,
-    bloom: Bloom,
+    pub gas_used: U256,
+    pub logs: Vec<Log>,
+    pub bloom: Bloom,
+}
+
+impl TransactionReceipt {
+    pub fn new(gas_used: U256, logs: Vec<Log>) -> TransactionReceipt {
+        let bloom = Bloom::from_logs(&logs);
+        TransactionReceipt { gas_used, logs, bloom }
+    }
 }
 
 #[derive(PartialEq, Clone)]
@@ -264,10 +398,10 @@ pub struct Contract {
     // caller
 }
 
-#[derive(PartialEq, Clone)]
+#[derive(PartialEq, Clone, Default)]
 pub struct Header {}
 
-#[derive(PartialEq, Clone)]
+#[derive(PartialEq, Clone, Default)]
 pub struct Env {
     /// I_a, the address of the account which owns the code that is executing
     owner: Address,
@@ -299,15 +433,68 @@ pub struct Env {
     /// I_e: the depth of the present message-call or contract-creation (ie the number of CALLs of
     /// CREATEs being executed at present)
     depth: u16,
+
+    /// I_w, the permission to make modifications to the state. False inside a STATICCALL.
+    is_static: bool,
+
+    /// The versioned hashes of the blobs carried by the transaction (EIP-4844)
+    blob_hashes: Vec<K256>,
+}
+
+impl Env {
+    /// The environment of a transaction from `origin` running code owned by `owner`, called by
+    /// `caller` with `transaction_value` and input `data`
+    pub fn new(owner: Address, origin: Address, caller: Address, gas_price: U256,
+               transaction_value: U256, data: Vec<u8>) -> Env {
+        Env {
+            owner,
+            origin,
+            gas_price,
+            data,
+            caller,
+            transaction_value,
+            code: Vec::new(),
+            header: Header {},
+            depth: 0,
+            is_static: false,
+            blob_hashes: Vec::new(),
+        }
+    }
+}
+
+/// A frame suspended while a message call it made is running, with everything needed to resume
+/// it afterwards
+struct Frame {
+    state: FrameState,
+    env: Env,
+    /// where in this frame's memory the callee's output goes
+    out_offset: usize,
+    out_len: usize,
+    /// the journal checkpoint and substate to go back to if the callee fails
+    checkpoint: usize,
+    substate: Substate,
+    /// the address of the account being created, if the callee is running init code
+    created: Option<Address>,
 }
 
-#[derive(PartialEq, Clone)]
 pub struct VM {
     result: Option<VMResult>,
+    /// H_RETURN, the output of the frame that halted last. Once `run` finishes it's the output of
+    /// the whole execution.
+    output: Vec<u8>,
     state:  FrameState,
-    // frames: Array<Frame>,
+    /// the callers of the current frame, innermost last
+    frames: Vec<Frame>,
     env: Env,
     block: Block,
+    world: Box<dyn StateBackend>,
+    journal: Journal,
+    substate: Substate,
+    fork: Hardfork,
+    precompiles: Precompiles,
+    block_hashes: Box<dyn BlockHashes>,
+    /// the analysis of the code each frame runs, so code that's called again isn't analysed again
+    code_cache: CodeCache,
 }
 
 // 0s: stop and arithmetic operations
@@ -336,6 +523,9 @@ pub const OR:     u8 = 0x17;
 pub const XOR:    u8 = 0x18;
 pub const NOT:    u8 = 0x19;
 pub const BYTE:   u8 = 0x1a;
+pub const SHL:    u8 = 0x1b;
+pub const SHR:    u8 = 0x1c;
+pub const SAR:    u8 = 0x1d;
 
 // 20s: sha3
 pub const SHA3: u8    = 0x20;
@@ -354,6 +544,9 @@ pub const CODECOPY:     u8 = 0x39;
 pub const GASPRICE:     u8 = 0x3a;
 pub const EXTCODESIZE:  u8 = 0x3b;
 pub const EXTCODECOPY:  u8 = 0x3c;
+pub const RETURNDATASIZE: u8 = 0x3d;
+pub const RETURNDATACOPY: u8 = 0x3e;
+pub const EXTCODEHASH:  u8 = 0x3f;
 
 // 40s: block information
 pub const BLOCKHASH:  u8 = 0x40;
@@ -362,18 +555,29 @@ pub const TIMESTAMP:  u8 = 0x42;
 pub const NUMBER:     u8 = 0x43;
 pub const DIFFICULTY: u8 = 0x44;
 pub const GASLIMIT:   u8 = 0x45;
+pub const CHAINID:    u8 = 0x46;
+pub const SELFBALANCE: u8 = 0x47;
+pub const BASEFEE:    u8 = 0x48;
+pub const BLOBHASH:   u8 = 0x49;
+pub const BLOBBASEFEE: u8 = 0x4a;
 
 // 50s: stack, memory, storage, and flow operations
 pub const POP:      u8 = 0x50;
 pub const MLOAD:    u8 = 0x51;
 pub const MSTORE:   u8 = 0x52;
 pub const MSTORE8:  u8 = 0x53;
-pub const JUMP:     u8 = 0x54;
-pub const JUMPI:    u8 = 0x55;
+pub const SLOAD:    u8 = 0x54;
+pub const SSTORE:   u8 = 0x55;
+pub const JUMP:     u8 = 0x56;
+pub const JUMPI:    u8 = 0x57;
 pub const PC:       u8 = 0x58;
 pub const MSIZE:    u8 = 0x59;
 pub const GAS:      u8 = 0x5a;
 pub const JUMPDEST: u8 = 0x5b;
+pub const TLOAD:    u8 = 0x5c;
+pub const TSTORE:   u8 = 0x5d;
+pub const MCOPY:    u8 = 0x5e;
+pub const PUSH0:    u8 = 0x5f;
 
 pub const PUSH1:  u8 = 0x60;
 pub const PUSH2:  u8 = 0x61;
@@ -446,11 +650,24 @@ pub const SWAP15: u8 = 0x9e;
 pub const SWAP16: u8 = 0x9f;
 
 // a0s: logging operations
-
-// Should these be 256 bit or smaller?
-fn memory_expansion(s: U256, f: U256, l: U256) -> U256 {
-    if l.is_zero() { s } else { max(s, (f + l) / U256::from(32)) }
-}
+pub const LOG0: u8 = 0xa0;
+pub const LOG1: u8 = 0xa1;
+pub const LOG2: u8 = 0xa2;
+pub const LOG3: u8 = 0xa3;
+pub const LOG4: u8 = 0xa4;
+
+// f0s: system operations
+pub const CREATE:       u8 = 0xf0;
+pub const CALL:         u8 = 0xf1;
+pub const CALLCODE:     u8 = 0xf2;
+pub const RETURN:       u8 = 0xf3;
+pub const DELEGATECALL: u8 = 0xf4;
+pub const CREATE2:      u8 = 0xf5;
+pub const STATICCALL:   u8 = 0xfa;
+pub const REVERT:       u8 = 0xfd;
+/// The designated invalid instruction
+pub const INVALID:      u8 = 0xfe;
+pub const SELFDESTRUCT: u8 = 0xff;
 
 fn bool_to_u256(b: bool) -> U256 {
     if b { U256::one() } else { U256::zero() }
@@ -460,32 +677,161 @@ fn addr_to_u256(&Address(bytes): &Address) -> U256 {
     return U256::from_big_endian(&bytes[0..20]);
 }
 
+/// The address given by the low 160 bits of a stack item
+fn u256_to_addr(value: U256) -> Address {
+    let mut bytes = [0u8; 32];
+    value.to_big_endian(&mut bytes);
+    let mut addr = [0u8; 20];
+    addr.copy_from_slice(&bytes[12..]);
+    Address(addr)
+}
+
+/// The 32 bytes of `data` starting at `offset`, reading zeros past the end
+fn load_word(data: &[u8], offset: U256) -> U256 {
+    let mut word = [0u8; 32];
+    if offset < U256::from(data.len()) {
+        let start = offset.low_u64() as usize;
+        for (i, byte) in data.iter().skip(start).take(32).enumerate() {
+            word[i] = *byte;
+        }
+    }
+    U256::from_big_endian(&word)
+}
+
+fn keccak(bytes: &[u8]) -> U256 {
+    let mut hasher = Keccak256::default();
+    hasher.input(bytes);
+    U256::from(hasher.result().as_slice())
+}
+
 fn big_to_u256(big: &BigUint) -> U256 {
     let bytes = big.to_bytes_be();
     return U256::from_big_endian(&bytes);
 }
 
+#[derive(PartialEq, Clone, Debug)]
 pub enum InstructionResult {
     Normal,
     Halt,
+    Revert,
 }
 
 impl VM {
-    pub fn step(&mut self) -> InstructionResult {
+    /// A VM about to run `code` with `gas` in `env`, against the state in `world`. It follows
+    /// Istanbul's rules until `set_fork` says otherwise.
+    pub fn new(code: Vec<u8>, gas: U256, mut env: Env, block: Block, world: Box<dyn StateBackend>) -> VM {
+        let code_cache = CodeCache::new();
+        let analysed = code_cache.analyze(K256(keccak(&code)), code.clone());
+        env.code = code;
+        VM {
+            result: None,
+            output: Vec::new(),
+            state: FrameState::new(analysed, gas),
+            frames: Vec::new(),
+            env,
+            block,
+            world,
+            journal: Journal::new(),
+            substate: Substate::default(),
+            fork: Hardfork::Istanbul,
+            precompiles: Precompiles::for_fork(Hardfork::Istanbul),
+            block_hashes: Box::new(InMemoryBlockHashes::new()),
+            code_cache,
+        }
+    }
+
+    /// Execute a single instruction. When a nested call halts, its caller carries on from the next
+    /// step. When the outermost frame halts exceptionally, all remaining gas is consumed and the
+    /// error is recorded in `result` as well as returned.
+    pub fn step(&mut self) -> Result<InstructionResult, VmError> {
+        match self.execute_instruction() {
+            Ok(InstructionResult::Normal) => Ok(InstructionResult::Normal),
+            Ok(InstructionResult::Halt) if !self.frames.is_empty() => {
+                self.return_to_caller(true);
+                Ok(InstructionResult::Normal)
+            },
+            Ok(InstructionResult::Revert) if !self.frames.is_empty() => {
+                self.return_to_caller(false);
+                Ok(InstructionResult::Normal)
+            },
+            Err(_) if !self.frames.is_empty() => {
+                // an exceptional halt only fails the call it happened in
+                self.state.gas_available = U256::zero();
+                self.output.clear();
+                self.return_to_caller(false);
+                Ok(InstructionResult::Normal)
+            },
+            Ok(InstructionResult::Halt) => {
+                for address in self.substate.selfdestructs.iter() {
+                    self.world.remove_account(address);
+                }
+                self.world.commit();
+                self.journal.clear_transient_storage();
+                self.result = Some(VMResult::VmSuccess);
+                Ok(InstructionResult::Halt)
+            },
+            Ok(InstructionResult::Revert) => {
+                self.journal.revert_to(0, &mut *self.world);
+                self.substate = Substate::default();
+                self.result = Some(VMResult::VmRevert);
+                Ok(InstructionResult::Revert)
+            },
+            Err(err) => {
+                self.state.gas_available = U256::zero();
+                self.output.clear();
+                self.journal.revert_to(0, &mut *self.world);
+                self.substate = Substate::default();
+                self.result = Some(VMResult::VmFailure(err.clone()));
+                Err(err)
+            },
+        }
+    }
+
+    fn execute_instruction(&mut self) -> Result<InstructionResult, VmError> {
         use InstructionResult::*;
 
         let pc    = self.state.pc;
-        let op    = self.state.code[pc];
+        // executing past the end of the code is the same as executing STOP
+        let op    = self.state.code.get(pc).unwrap_or(STOP);
         let state = &mut self.state;
 
-        if op >= PUSH1 && op <= PUSH32 {
-            let n    = usize::from(op - PUSH1 + 1);
-            let val1 = &state.code[pc+1..pc+n+1];
-            let val  = U256::from_big_endian(val1);
+        let info = opcodes::info(op).ok_or(VmError::InvalidOpcode(op))?;
+        if !self.fork.has_opcode(op) {
+            return Err(VmError::InvalidOpcode(op));
+        }
+        state.stack.check(info.inputs, info.outputs)?;
+        if info.writes_state() && self.env.is_static {
+            return Err(VmError::WriteProtection);
+        }
+
+        state.use_gas(gas::cost(op, &state.stack, self.fork))?;
+        if self.fork >= Hardfork::Berlin {
+            let cold = match gas::access(op, &state.stack) {
+                Some(gas::Access::Account(address)) => self.substate.accessed_addresses.insert(address),
+                Some(gas::Access::Storage(key)) =>
+                    self.substate.accessed_storage_keys.insert((self.env.owner.clone(), key)),
+                None => false,
+            };
+            if cold {
+                state.use_gas(U256::from(gas::cold_access_cost(op)))?;
+            }
+        }
+
+        if let Some((offset, len)) = gas::memory_range(op, &state.stack) {
+            state.expand_memory(offset, len)?;
+        }
+
+        if info.immediate > 0 {
+            // immediate bytes that run past the end of the code are read as zeros
+            let n       = info.immediate;
+            let mut imm = [0u8; 32];
+            for (i, byte) in state.code.bytes().iter().skip(pc + 1).take(n).enumerate() {
+                imm[32 - n + i] = *byte;
+            }
             state.pc += n; // pc will also be incremented by one
-            state.stack.push(val);
+            state.stack.push(U256::from_big_endian(&imm));
         } else if op >= DUP1 && op <= DUP16 {
-            let n   = usize::from(op - DUP1 + 1);
+            let n   = usize::from(op - DUP1);
             let val = state.stack[n];
             state.stack.push(val);
         } else if op >= SWAP1 && op <= SWAP16 {
@@ -493,16 +839,35 @@ impl VM {
             let tmp = state.stack[n];
             state.stack[n]  = state.stack[0];
             state.stack[0]  = tmp;
+        } else if (LOG0..=LOG4).contains(&op) {
+            let offset = state.stack[0].low_u64() as usize;
+            let len    = state.stack[1].low_u64() as usize;
+            let n      = usize::from(op - LOG0);
+            let topics = (0..n).map(|i| K256(state.stack[i + 2])).collect();
+            state.stack.pop(n + 2);
+            let index = self.substate.logs.len() as u32;
+            self.substate.logs.push(Log {
+                address: self.env.owner.clone(),
+                topics,
+                data: state.memory.slice(offset, len).to_vec(),
+                block_number: self.block.number.low_u64(),
+                // XXX we don't know which transaction or block we're executing
+                tx_hash: K256(U256::zero()),
+                tx_index: 0,
+                block_hash: K256(U256::zero()),
+                index,
+                removed: false,
+            });
         } else {
 
         match op {
-            STOP => { return Halt; },
+            STOP => { return Ok(Halt); },
 
-            ADD => state.stack.apply_binary_op(Add::add),
+            ADD => state.stack.apply_binary_op(|s0, s1| s0.overflowing_add(s1).0),
 
-            MUL => state.stack.apply_binary_op(Mul::mul),
+            MUL => state.stack.apply_binary_op(|s0, s1| s0.overflowing_mul(s1).0),
 
-            SUB => state.stack.apply_binary_op(Sub::sub),
+            SUB => state.stack.apply_binary_op(|s0, s1| s0.overflowing_sub(s1).0),
 
             DIV => state.stack.apply_binary_op(|s0, s1|
                 if s1.is_zero() {
@@ -512,26 +877,7 @@ impl VM {
                 }
             ),
 
-            SDIV => state.stack.apply_binary_op(|s0,s1|
-                 if s1.is_zero() {
-                     U256::zero()
-                 } else {
-                     let min_value = (U256::one() << 255) - U256::one();
-                     let negative_one = !U256::zero();
-
-                     if s0 == min_value && s1 == negative_one {
-                         min_value
-                     } else {
-                         let divisor = S256(S256(s0).abs() / S256(s1).abs());
-
-                         if S256(s0).sign() != S256(s1).sign() {
-                             divisor.invert().to_u256()
-                         } else {
-                             divisor.to_u256()
-                         }
-                     }
-                 }
-            ),
+            SDIV => state.stack.apply_binary_op(|s0, s1| (I256::from(s0) / I256::from(s1)).into()),
 
             MOD        => state.stack.apply_binary_op(|s0, s1|
                        if s1.is_zero() {
@@ -541,36 +887,13 @@ impl VM {
                        }
             ),
 
-            SMOD       => state.stack.apply_binary_op(|s0,s1|
-                       if s1.is_zero() {
-                           U256::zero()
-                       } else {
-                           let res = S256(s0).abs() % S256(s1).abs();
-                           if S256(s0).sign() {
-                               let res = !res + U256::one();
-                           }
-                           res
-                       }
-            ),
+            SMOD       => state.stack.apply_binary_op(|s0, s1| (I256::from(s0) % I256::from(s1)).into()),
 
             // Intermediate calculations not subject to 2^256 modulo
-            ADDMOD     => state.stack.apply_ternary_op(|s0, s1, s2|
-                       if s2.is_zero() {
-                           U256::zero()
-                       } else {
-
-                           (s0 + s1) % s2
-                       }
-            ),
+            ADDMOD     => state.stack.apply_ternary_op(wide::addmod),
 
             // Again, intermediates not subject to 2^256 mod
-            MULMOD     => state.stack.apply_ternary_op(|s0, s1, s2|
-                       if s2.is_zero() {
-                           U256::zero()
-                       } else {
-                           (s0 * s1) % s2
-                       }
-            ),
+            MULMOD     => state.stack.apply_ternary_op(wide::mulmod),
 
             //Overflowing pow returns (result,overflow_bool)
             EXP        => state.stack.apply_binary_op(|s0, s1| {s0.overflowing_pow(s1).0}),
@@ -585,7 +908,8 @@ impl VM {
                                s1 & mask
                            }
                        } else {
-                           s0
+                           // the sign bit is already the top bit
+                           s1
                        }
             ),
 
@@ -594,29 +918,9 @@ impl VM {
 
             GT => state.stack.apply_binary_op(|x, y| bool_to_u256(x > y)),
 
-            SLT => state.stack.apply_binary_op(|x, y|
-                {
-                    let res = match (S256(x).sign(),S256(y).sign()) {
-                        (false,false) => x < y,
-                        (false,true)  => false,
-                        (true,false)  => true,
-                        (true,true)   => S256(x).abs() >= S256(y).abs(),
-                    };
-                    bool_to_u256(res)
-                }
-            ),
+            SLT => state.stack.apply_binary_op(|x, y| bool_to_u256(I256::from(x) < I256::from(y))),
 
-            SGT => state.stack.apply_binary_op(|x, y|
-                {
-                    let res = match (S256(x).sign(),S256(y).sign()) {
-                        (false,false) => x > y,
-                        (false,true)  => true,
-                        (true,false)  => false,
-                        (true,true)   => S256(x).abs() <= S256(y).abs(),
-                    };
-                    bool_to_u256(res)
-                }
-            ),
+            SGT => state.stack.apply_binary_op(|x, y| bool_to_u256(I256::from(x) > I256::from(y))),
 
             EQ => state.stack.apply_binary_op(|x, y| bool_to_u256(x == y)),
 
@@ -632,33 +936,37 @@ impl VM {
 
             BYTE => {
                 let stk    = &mut state.stack;
-                let ix     = stk[0].as_u64() as usize;
+                let ix     = stk[0];
                 let source = stk[1];
                 stk.pop(2);
-                stk.push(U256::from_big_endian(&[source.byte(ix)]));
+                // byte 0 is the most significant; U256::byte counts from the least
+                let byte = if ix < U256::from(32) { source.byte(31 - ix.low_u64() as usize) } else { 0 };
+                stk.push(U256::from(byte));
             },
 
-//            SHA3 => {
-//                let mut hasher = Keccak256::default();
-//                let     stt = self.state.borrow_mut();
-//                let mut stk = stt.stack.borrow_mut();
-//                let start = stk[0];
-//                let end   = stk[1];
-//                let message = self.get_memory(start, end);
-//                hasher.input(message);
-//                let out = hasher.result();
+            // EIP-145: the shift comes first, then the value shifted
+            SHL => state.stack.apply_binary_op(|shift, value|
+                if shift >= U256::from(256) { U256::zero() } else { value << shift.low_u64() as usize }),
+
+            SHR => state.stack.apply_binary_op(|shift, value|
+                if shift >= U256::from(256) { U256::zero() } else { value >> shift.low_u64() as usize }),
 
-//                stk.pop(2);
-//                stk.push(out);
-//                stt.active_words = memory_expansion(stt.active_words, start, end);
-//            }
+            SAR => state.stack.apply_binary_op(|shift, value| I256::from(value).sar(shift).into()),
+
+            SHA3 => {
+                let start = state.stack[0].low_u64() as usize;
+                let len   = state.stack[1].low_u64() as usize;
+                let out   = keccak(state.memory.slice(start, len));
+                state.stack.pop(2);
+                state.stack.push(out);
+            },
 
             ADDRESS => state.stack.push(addr_to_u256(&self.env.owner)),
 
-//             BALANCE => {
-//                 let addr =
-//                 self.state
-//             }
+            BALANCE => {
+                let addr = u256_to_addr(state.stack[0]);
+                state.stack[0] = self.world.balance(&addr);
+            },
 
             ORIGIN => state.stack.push(addr_to_u256(&self.env.origin)),
 
@@ -666,26 +974,85 @@ impl VM {
 
             CALLVALUE => state.stack.push(self.env.transaction_value),
 
-//             CALLDATALOAD => {
-//                 let ix = &mut self.state.stack[0];
-//                 let data = self.env.data;
-//                 // XXX default to 0 for index out of bounds
-//                 let mut slice = data[ix..ix+32];
-//                 self.state.stack.pop(1);
-//                 self.state.stack.push(U256::from(slice));
-//             }
+            CALLDATALOAD => state.stack[0] = load_word(&self.env.data, state.stack[0]),
 
             CALLDATASIZE =>
                 state.stack.push(U256::from(self.env.data.len())),
 
-            // CALLDATACOPY => {}
-            // CODESIZE => {}
-            // CODECOPY => {}
-            // GASPRICE => {}
-            // EXTCODESIZE => {}
-            // EXTCODECOPY => {}
+            CALLDATACOPY => {
+                let mem_offset  = state.stack[0].low_u64() as usize;
+                let data_offset = state.stack[1];
+                let len         = state.stack[2].low_u64() as usize;
+                state.stack.pop(3);
+                state.memory.copy_padded(mem_offset, &self.env.data, data_offset, len);
+            },
+
+            CODESIZE => {
+                let size = state.code.len();
+                state.stack.push(U256::from(size));
+            },
+
+            CODECOPY => {
+                let mem_offset  = state.stack[0].low_u64() as usize;
+                let code_offset = state.stack[1];
+                let len         = state.stack[2].low_u64() as usize;
+                state.stack.pop(3);
+                state.memory.copy_padded(mem_offset, state.code.bytes(), code_offset, len);
+            },
+
+            GASPRICE => state.stack.push(self.env.gas_price),
+
+            RETURNDATASIZE => state.stack.push(U256::from(state.return_data.len())),
+
+            RETURNDATACOPY => {
+                let mem_offset  = state.stack[0].low_u64() as usize;
+                let data_offset = state.stack[1];
+                let len         = state.stack[2];
+                state.stack.pop(3);
+                // unlike the other copies, reading past the end is an exceptional halt
+                match data_offset.overflowing_add(len) {
+                    (end, false) if end <= U256::from(state.return_data.len()) => {},
+                    _ => return Err(VmError::ReturnDataOutOfBounds),
+                }
+                let len = len.low_u64() as usize;
+                state.memory.copy_padded(mem_offset, &state.return_data, data_offset, len);
+            },
+
+            EXTCODESIZE => {
+                let addr = u256_to_addr(state.stack[0]);
+                state.stack[0] = U256::from(self.world.code(&addr).len());
+            },
+
+            EXTCODECOPY => {
+                let addr        = u256_to_addr(state.stack[0]);
+                let mem_offset  = state.stack[1].low_u64() as usize;
+                let code_offset = state.stack[2];
+                let len         = state.stack[3].low_u64() as usize;
+                state.stack.pop(4);
+                let code = self.world.code(&addr);
+                state.memory.copy_padded(mem_offset, &code, code_offset, len);
+            },
+
+            // EIP-1052: zero for accounts that don't exist or are empty
+            EXTCODEHASH => {
+                let addr = u256_to_addr(state.stack[0]);
+                state.stack[0] = if self.world.is_empty(&addr) {
+                    U256::zero()
+                } else {
+                    self.world.code_hash(&addr).0
+                };
+            },
 
-            BLOCKHASH => panic!("unimplemented: BLOCKHASH"),
+            // only the 256 blocks before this one have a hash, and the rest are zero
+            BLOCKHASH => {
+                let number  = state.stack[0];
+                let current = self.block.number;
+                state.stack[0] = if number < current && current - number <= U256::from(BLOCKHASH_WINDOW) {
+                    self.block_hashes.block_hash(&*self.world, number.low_u64()).0
+                } else {
+                    U256::zero()
+                };
+            },
 
             COINBASE =>
                 state.stack.push(addr_to_u256(&self.block.beneficiary)),
@@ -702,36 +1069,104 @@ impl VM {
             GASLIMIT =>
                 state.stack.push(self.block.gas_limit),
 
+            CHAINID =>
+                state.stack.push(self.block.chain_id),
+
+            SELFBALANCE =>
+                state.stack.push(self.world.balance(&self.env.owner)),
+
+            BASEFEE =>
+                state.stack.push(self.block.base_fee),
+
+            // EIP-4844: zero past the end of the transaction's blobs
+            BLOBHASH => {
+                let ix = state.stack[0];
+                state.stack[0] = if ix < U256::from(self.env.blob_hashes.len()) {
+                    self.env.blob_hashes[ix.low_u64() as usize].0
+                } else {
+                    U256::zero()
+                };
+            },
+
+            BLOBBASEFEE =>
+                state.stack.push(self.block.blob_base_fee),
+
+            PUSH0 => state.stack.push(U256::zero()),
+
             POP => state.stack.pop(1),
 
+            // memory has already been expanded to cover these offsets, so they fit in a usize
             MLOAD => {
-                let loc = state.stack[0].low_u32() as usize;
-                state.stack[0] = state.m_load(loc);
+                let loc = state.stack[0].low_u64() as usize;
+                state.stack[0] = state.memory.load(loc);
             },
 
             MSTORE => {
-                let loc = state.stack[0].low_u32() as usize;
+                let loc = state.stack[0].low_u64() as usize;
                 let val = state.stack[1];
 
-                state.m_store(loc, val);
+                state.memory.store(loc, val);
 
                 state.stack.pop(2);
             },
 
             MSTORE8 => {
-                let loc = state.stack[0].low_u32() as usize;
+                let loc = state.stack[0].low_u64() as usize;
                 let val = state.stack[1].low_u32() as u8;
 
-                state.m_store8(loc, val);
+                state.memory.store8(loc, val);
 
                 state.stack.pop(2);
             },
 
+            SLOAD => state.stack[0] = self.world.storage(&self.env.owner, state.stack[0]),
+
+            SSTORE => {
+                // EIP-2200: SSTORE fails if it could leave less than a call stipend of gas
+                if self.fork >= Hardfork::Istanbul && state.gas_available <= U256::from(gas::G_CALLSTIPEND) {
+                    return Err(VmError::OutOfGas);
+                }
+                let key      = state.stack[0];
+                let new      = state.stack[1];
+                if self.fork >= Hardfork::Berlin
+                    && self.substate.accessed_storage_keys.insert((self.env.owner.clone(), key)) {
+                    state.use_gas(U256::from(gas::G_COLDSLOAD))?;
+                }
+                let current  = self.world.storage(&self.env.owner, key);
+                let original = self.journal.original_storage(&*self.world, &self.env.owner, key);
+                let (cost, refund) = gas::sstore_cost(self.fork, original, current, new);
+                state.use_gas(U256::from(cost))?;
+                self.substate.refund += refund;
+                self.journal.set_storage(&mut *self.world, &self.env.owner, key, new);
+                state.stack.pop(2);
+            },
+
+            TLOAD => state.stack[0] = self.journal.transient_storage(&self.env.owner, state.stack[0]),
+
+            TSTORE => {
+                self.journal.set_transient_storage(&self.env.owner, state.stack[0], state.stack[1]);
+                state.stack.pop(2);
+            },
+
+            // memory has already been expanded to cover both regions
+            MCOPY => {
+                let dst = state.stack[0].low_u64() as usize;
+                let src = state.stack[1].low_u64() as usize;
+                let len = state.stack[2].low_u64() as usize;
+                state.stack.pop(3);
+                if len != 0 {
+                    state.memory.copy_within(dst, src, len);
+                }
+            },
+
             JUMP => {
                 let loc = state.stack[0];
                 state.stack.pop(1);
+                if !state.code.is_valid_jump(loc) {
+                    return Err(VmError::InvalidJump);
+                }
                 state.pc = loc.low_u64() as usize;
-                return Normal;
+                return Ok(Normal);
             },
 
             JUMPI => {
@@ -740,117 +1175,1865 @@ impl VM {
                 state.stack.pop(2);
 
                 if b != U256::zero() {
+                    if !state.code.is_valid_jump(loc) {
+                        return Err(VmError::InvalidJump);
+                    }
                     state.pc = loc.low_u64() as usize;
-                    return Normal;
+                    return Ok(Normal);
                 };
             },
 
             PC => state.stack.push(U256::from(pc)),
 
-            MSIZE => state.stack
-                .push(U256::from(state.memory.len() * 32)),
+            MSIZE => state.stack.push(U256::from(state.memory.len())),
 
             GAS => state.stack.push(state.gas_available),
 
             JUMPDEST => {}
 
-            _ => panic!("unimplemented instruction: {}", op),
+            CREATE | CREATE2 => return self.create(op),
+
+            CALL | CALLCODE | DELEGATECALL | STATICCALL => return self.call(op),
+
+            RETURN | REVERT => {
+                let offset = state.stack[0].low_u64() as usize;
+                let len    = state.stack[1].low_u64() as usize;
+                state.stack.pop(2);
+                self.output = state.memory.slice(offset, len).to_vec();
+                return Ok(if op == RETURN { Halt } else { Revert });
+            },
+
+            INVALID => return Err(VmError::InvalidOpcode(op)),
+
+            SELFDESTRUCT => return self.selfdestruct(),
+
+            _ => return Err(VmError::InvalidOpcode(op)),
         }
         };
 
         state.pc += 1;
 
-        Normal
+        Ok(Normal)
     }
 
-    pub fn run(&mut self) {
-        while self.state.pc < self.state.code.len() {
-            self.step();
+    /// CALL, CALLCODE, DELEGATECALL and STATICCALL (section 8 of the Yellow Paper). The current
+    /// frame is suspended and the callee's first instruction runs at the next step.
+    fn call(&mut self, op: u8) -> Result<InstructionResult, VmError> {
+        let stack     = &self.state.stack;
+        let requested = stack[0];
+        let to        = u256_to_addr(stack[1]);
+        // DELEGATECALL and STATICCALL don't take a value
+        let (value, n) = if op == CALL || op == CALLCODE { (stack[2], 3) } else { (U256::zero(), 2) };
+        let (in_offset, in_len)   = (stack[n], stack[n + 1]);
+        let (out_offset, out_len) = (stack[n + 2], stack[n + 3]);
+        self.state.stack.pop(n + 4);
+
+        // CALLCODE only sends value to the caller itself, so it's allowed
+        if op == CALL && self.env.is_static && !value.is_zero() {
+            return Err(VmError::WriteProtection);
+        }
+
+        self.state.expand_memory(in_offset, in_len)?;
+        self.state.expand_memory(out_offset, out_len)?;
+
+        // EIP-161: only sending value to a dead account brings a new one into being. Before
+        // Spurious Dragon any call to an account that doesn't exist created it.
+        let creates_account = if self.fork >= Hardfork::SpuriousDragon {
+            !value.is_zero() && self.world.is_dead(&to)
+        } else {
+            !self.world.exists(&to)
+        };
+        if op == CALL && creates_account {
+            self.state.use_gas(U256::from(gas::G_NEWACCOUNT))?;
+        }
+
+        // EIP-150: the callee gets at most all but one 64th of the gas that's left, plus a
+        // stipend it doesn't pay for if value is sent. Before Tangerine Whistle it got exactly
+        // what was asked for, or the caller ran out of gas.
+        let gas = if self.fork >= Hardfork::TangerineWhistle {
+            cmp::min(requested, gas::all_but_one_64th(self.state.gas_available))
+        } else {
+            requested
+        };
+        self.state.use_gas(gas)?;
+        let gas = if value.is_zero() { gas } else { gas.saturating_add(U256::from(gas::G_CALLSTIPEND)) };
+
+        self.state.pc += 1;
+
+        if self.env.depth >= CALL_DEPTH_LIMIT || value > self.world.balance(&self.env.owner) {
+            // the call fails without running, which isn't an exceptional halt of the caller
+            self.state.gas_available = self.state.gas_available.saturating_add(gas);
+            self.state.return_data = Vec::new();
+            self.state.stack.push(U256::zero());
+            return Ok(InstructionResult::Normal);
+        }
+
+        let data = if in_len.is_zero() {
+            Vec::new()
+        } else {
+            self.state.memory.slice(in_offset.low_u64() as usize, in_len.low_u64() as usize).to_vec()
+        };
+        // a precompile runs natively in place of code, but otherwise goes through a frame like any
+        // other call so value transfers and reverts work the same
+        let precompiled = self.precompiles.get(&to).map(|precompile| {
+            let cost = precompile.gas(&data);
+            if cost > gas {
+                None
+            } else {
+                precompile.execute(&data).map(|output| (gas - cost, output))
+            }
+        });
+        let code = self.world.code(&to);
+        let env = Env {
+            owner:     if op == CALL || op == STATICCALL { to.clone() } else { self.env.owner.clone() },
+            origin:    self.env.origin.clone(),
+            gas_price: self.env.gas_price,
+            data,
+            caller:    if op == DELEGATECALL { self.env.caller.clone() } else { self.env.owner.clone() },
+            transaction_value: if op == DELEGATECALL { self.env.transaction_value } else { value },
+            code:      code.clone(),
+            header:    self.env.header.clone(),
+            depth:     self.env.depth + 1,
+            is_static: self.env.is_static || op == STATICCALL,
+            blob_hashes: self.env.blob_hashes.clone(),
+        };
+        let code = self.code_cache.analyze(self.world.code_hash(&to), code);
+        let state = FrameState::new(code, gas);
+
+        let checkpoint = self.journal.checkpoint();
+        // CALLCODE sends the value to the caller itself, which changes nothing
+        if op == CALL && !value.is_zero() {
+            self.journal.transfer(&mut *self.world, &self.env.owner, &to, value);
+        }
+
+        self.frames.push(Frame {
+            state: mem::replace(&mut self.state, state),
+            env: mem::replace(&mut self.env, env),
+            out_offset: if out_len.is_zero() { 0 } else { out_offset.low_u64() as usize },
+            out_len: out_len.low_u64() as usize,
+            checkpoint,
+            substate: self.substate.clone(),
+            created: None,
+        });
+        match precompiled {
+            Some(Some((gas_left, output))) => {
+                self.state.gas_available = gas_left;
+                self.output = output;
+                self.return_to_caller(true);
+            },
+            Some(None) => {
+                self.state.gas_available = U256::zero();
+                self.output.clear();
+                self.return_to_caller(false);
+            },
+            None => (),
         }
+        Ok(InstructionResult::Normal)
     }
-}
 
-fn init_vm(code: &Vec<u8>, gas: u32) -> VM {
-    VM {
-        result: None,
-        state: FrameState {
-            code:          code.clone(),
-            gas_available: U256::from(gas),
-            pc:            0,
-            memory:        Vec::new(),
-            active_words:  U256::zero(),
-            stack:         Stack::new(),
-        },
-        env: Env {
-            owner: Address([0; 20]),
-            origin: Address([0; 20]),
-            gas_price: U256::zero(),
-            data: Vec::new(),
-            caller: Address([0; 20]),
-            transaction_value: U256::zero(),
-            code: Vec::new(),
-            header: Header {},
-            depth: 0,
-        },
-        block: Block {
-            beneficiary: Address([0; 20]),
-            difficulty: U256::one(),
-            number: U256::one(),
-            gas_limit: U256::one(),
-            timestamp: U256::one(),
+    /// CREATE and CREATE2 (section 7 of the Yellow Paper). The init code runs in a new frame from
+    /// the next step, and whatever it outputs becomes the new account's code.
+    fn create(&mut self, op: u8) -> Result<InstructionResult, VmError> {
+        let value  = self.state.stack[0];
+        let offset = self.state.stack[1];
+        let len    = self.state.stack[2];
+        let salt   = if op == CREATE2 { Some(self.state.stack[3]) } else { None };
+        self.state.stack.pop(if op == CREATE2 { 4 } else { 3 });
+        // EIP-3860: init code is limited to twice the size of deployed code
+        if self.fork >= Hardfork::Shanghai && len > U256::from(2 * MAX_CODE_SIZE) {
+            return Err(VmError::OutOfGas);
+        }
+        // memory has already been expanded to cover the init code
+        let init_code = if len.is_zero() {
+            Vec::new()
+        } else {
+            self.state.memory.slice(offset.low_u64() as usize, len.low_u64() as usize).to_vec()
+        };
+
+        // EIP-150: the init code gets all but one 64th of the gas that's left, where before
+        // Tangerine Whistle it got all of it
+        let gas = if self.fork >= Hardfork::TangerineWhistle {
+            gas::all_but_one_64th(self.state.gas_available)
+        } else {
+            self.state.gas_available
+        };
+        self.state.use_gas(gas)?;
+
+        self.state.pc += 1;
+
+        let sender = self.env.owner.clone();
+        let nonce  = self.world.nonce(&sender);
+        if self.env.depth >= CALL_DEPTH_LIMIT || value > self.world.balance(&sender) || nonce == u64::MAX {
+            // the creation fails without running, which isn't an exceptional halt of the creator
+            self.state.gas_available = self.state.gas_available.saturating_add(gas);
+            self.state.return_data = Vec::new();
+            self.state.stack.push(U256::zero());
+            return Ok(InstructionResult::Normal);
+        }
+
+        self.journal.set_nonce(&mut *self.world, &sender, nonce + 1);
+        let address = match salt {
+            Some(salt) => create2_address(&sender, salt, &init_code),
+            None       => create_address(&sender, nonce),
+        };
+
+        // EIP-684: creating an account where there's already a contract fails, using up the gas
+        // set aside for the init code
+        if self.world.nonce(&address) != 0 || !self.world.code(&address).is_empty() {
+            self.state.return_data = Vec::new();
+            self.state.stack.push(U256::zero());
+            return Ok(InstructionResult::Normal);
+        }
+
+        let checkpoint = self.journal.checkpoint();
+        self.substate.accessed_addresses.insert(address.clone());
+        // EIP-161: contracts start with a nonce of one. Any balance already sent to the address is
+        // kept.
+        if self.fork >= Hardfork::SpuriousDragon {
+            self.journal.set_nonce(&mut *self.world, &address, 1);
+        }
+        if !value.is_zero() {
+            self.journal.transfer(&mut *self.world, &sender, &address, value);
+        }
+
+        let env = Env {
+            owner:     address.clone(),
+            origin:    self.env.origin.clone(),
+            gas_price: self.env.gas_price,
+            data:      Vec::new(),
+            caller:    sender,
+            transaction_value: value,
+            code:      init_code.clone(),
+            header:    self.env.header.clone(),
+            depth:     self.env.depth + 1,
+            is_static: false,
+            blob_hashes: self.env.blob_hashes.clone(),
+        };
+        let init_code = self.code_cache.analyze(K256(keccak(&init_code)), init_code);
+        let state = FrameState::new(init_code, gas);
+
+        self.frames.push(Frame {
+            state: mem::replace(&mut self.state, state),
+            env: mem::replace(&mut self.env, env),
+            out_offset: 0,
+            out_len: 0,
+            checkpoint,
+            substate: self.substate.clone(),
+            created: Some(address.clone()),
+        });
+        self.substate.created.insert(address);
+        Ok(InstructionResult::Normal)
+    }
+
+    /// SELFDESTRUCT: send the whole balance to the beneficiary and halt. The account is deleted
+    /// when the transaction completes, though from Cancun (EIP-6780) only if it was created in the
+    /// same transaction.
+    fn selfdestruct(&mut self) -> Result<InstructionResult, VmError> {
+        let beneficiary = u256_to_addr(self.state.stack[0]);
+        self.state.stack.pop(1);
+        let owner   = self.env.owner.clone();
+        let balance = self.world.balance(&owner);
+
+        let creates_account = if self.fork >= Hardfork::SpuriousDragon {
+            !balance.is_zero() && self.world.is_dead(&beneficiary)
+        } else {
+            !self.world.exists(&beneficiary)
+        };
+        self.state.use_gas(U256::from(gas::selfdestruct_cost(self.fork, creates_account)))?;
+
+        // EIP-161: sending nothing doesn't touch the beneficiary
+        if !balance.is_zero() || self.fork < Hardfork::SpuriousDragon {
+            self.journal.set_balance(&mut *self.world, &owner, U256::zero());
+            let beneficiary_balance = self.world.balance(&beneficiary);
+            self.journal.set_balance(&mut *self.world, &beneficiary, beneficiary_balance.saturating_add(balance));
+        }
+
+        if self.fork < Hardfork::Cancun || self.substate.created.contains(&owner) {
+            // the refund is only given once per account
+            if self.substate.selfdestructs.insert(owner) {
+                self.substate.refund += gas::selfdestruct_refund(self.fork);
+            }
+        }
+
+        self.output.clear();
+        Ok(InstructionResult::Halt)
+    }
+
+    /// Resume the caller once the current frame has halted, with `output` holding what it
+    /// returned. If it failed, everything it did is undone. Its unused gas goes back to the
+    /// caller. The output of a call is copied into the memory the caller set aside for it, as much
+    /// as fits, and the output of init code is deployed as the new account's code if there's gas
+    /// left to pay for it.
+    fn return_to_caller(&mut self, success: bool) {
+        let frame        = self.frames.pop().expect("no frame to return to");
+        let mut output   = mem::take(&mut self.output);
+        let mut gas_left = self.state.gas_available;
+        let mut success  = success;
+
+        if let (true, Some(address)) = (success, &frame.created) {
+            let cost = gas::code_deposit_cost(output.len());
+            // EIP-170 limits the size of code, and EIP-3541 reserves code starting with 0xef
+            let invalid = (self.fork >= Hardfork::SpuriousDragon && output.len() > MAX_CODE_SIZE)
+                || (self.fork >= Hardfork::London && output.first() == Some(&0xef));
+            if cost > gas_left && self.fork == Hardfork::Frontier && !invalid {
+                // in Frontier, init code that can't pay for its code still creates the account,
+                // just without any code
+                output = Vec::new();
+            } else if invalid || cost > gas_left {
+                success  = false;
+                gas_left = U256::zero();
+                output   = Vec::new();
+            } else {
+                gas_left = gas_left - cost;
+                self.journal.set_code(&mut *self.world, address, output.clone());
+            }
+        }
+
+        if !success {
+            self.journal.revert_to(frame.checkpoint, &mut *self.world);
+            self.substate = frame.substate;
+        }
+        self.state = frame.state;
+        self.env   = frame.env;
+        self.state.gas_available = self.state.gas_available.saturating_add(gas_left);
+
+        match frame.created {
+            Some(ref address) if success => {
+                self.state.stack.push(addr_to_u256(address));
+                self.state.return_data = Vec::new();
+            },
+            // only init code that REVERTs leaves any return data
+            Some(_) => {
+                self.state.stack.push(U256::zero());
+                self.state.return_data = output;
+            },
+            None => {
+                let len = cmp::min(output.len(), frame.out_len);
+                self.state.memory.copy_padded(frame.out_offset, &output, U256::zero(), len);
+                self.state.stack.push(bool_to_u256(success));
+                self.state.return_data = output;
+            },
+        }
+    }
+
+    /// The output of the execution, set by RETURN or REVERT
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// The accounts that self-destructed, which are deleted once execution completes successfully
+    pub fn selfdestructs(&self) -> &HashSet<Address> {
+        &self.substate.selfdestructs
+    }
+
+    /// The logs emitted so far
+    pub fn logs(&self) -> &[Log] {
+        &self.substate.logs
+    }
+
+    /// The gas given back to the sender of a transaction that used `gas_used` in all, including
+    /// its intrinsic gas, which the VM doesn't see: the refund counter, capped by `gas::max_refund`.
+    /// It's zero if the execution didn't halt normally.
+    pub fn refund(&self, gas_used: U256) -> U256 {
+        let counter = U256::from(cmp::max(self.substate.refund, 0) as u64);
+        cmp::min(counter, gas::max_refund(self.fork, gas_used))
+    }
+
+    /// The world state, with every change execution has made so far
+    pub fn world(&self) -> &dyn StateBackend {
+        &*self.world
+    }
+
+    /// Execute under the rules of `fork`, with the precompiled contracts it has. Point evaluation
+    /// needs a trusted setup, so from Cancun it has to be added with `set_precompiles`.
+    pub fn set_fork(&mut self, fork: Hardfork) {
+        self.fork = fork;
+        self.precompiles = Precompiles::for_fork(fork);
+    }
+
+    /// Replace the precompiled contracts, for instance with the ones from
+    /// `Precompiles::for_fork_with_trusted_setup`. `set_fork` puts back the fork's own, so this
+    /// comes after it.
+    pub fn set_precompiles(&mut self, precompiles: Precompiles) {
+        self.precompiles = precompiles;
+    }
+
+    /// Where BLOCKHASH gets the hashes of earlier blocks from
+    pub fn set_block_hashes(&mut self, block_hashes: Box<dyn BlockHashes>) {
+        self.block_hashes = block_hashes;
+    }
+
+    /// Share `cache` with this VM, so code that's been analysed before, by this VM or any other
+    /// using the cache, isn't analysed again
+    pub fn set_code_cache(&mut self, cache: CodeCache) {
+        self.code_cache = cache;
+    }
+
+    /// Run until the code halts, normally or exceptionally. Halting with REVERT isn't an error;
+    /// `result` tells it apart from a normal halt.
+    pub fn run(&mut self) -> Result<(), VmError> {
+        // EIP-2929: the sender, the account being run and the precompiles start out warm, and from
+        // Shanghai (EIP-3651) the coinbase too
+        if self.fork >= Hardfork::Berlin {
+            let accessed = &mut self.substate.accessed_addresses;
+            accessed.insert(self.env.origin.clone());
+            accessed.insert(self.env.owner.clone());
+            accessed.extend(self.precompiles.addresses().cloned());
+            if self.fork >= Hardfork::Shanghai {
+                accessed.insert(self.block.beneficiary.clone());
+            }
         }
+        while self.step()? == InstructionResult::Normal {}
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use *;
+    use state::{InMemoryState, create_address, create2_address};
+    use std::mem;
+    use data_encoding::HEXLOWER;
+
+    const INVALID_OPCODE: u8 = 0xef;
+
+    fn init_vm(code: &Vec<u8>, gas: u32) -> VM {
+        let block = Block {
+            difficulty: U256::one(),
+            number: U256::one(),
+            gas_limit: U256::one(),
+            timestamp: U256::one(),
+            chain_id: U256::one(),
+            blob_base_fee: U256::one(),
+            ..Block::default()
+        };
+        VM::new(code.clone(), U256::from(gas), Env::default(), block, Box::new(InMemoryState::new()))
+    }
+
+    fn hex_u256(hex: &str) -> U256 {
+        U256::from(HEXLOWER.decode(hex.as_bytes()).unwrap().as_slice())
+    }
 
     #[test]
     fn it_works() {
         let mut vm = init_vm(&vec![PUSH1, 1, PUSH1, 2, ADD], 100);
-        vm.run();
+        vm.run().unwrap();
         assert_eq!(vm.state.stack[0].as_u32(), 3);
 
         let mut vm = init_vm(&vec![PUSH1, 1, PUSH1, 2, MUL], 100);
-        vm.run();
+        vm.run().unwrap();
         assert_eq!(vm.state.stack[0].as_u32(), 2);
 
         let mut vm = init_vm(&vec![PUSH1, 1, PUSH1, 2, SUB], 100);
-        vm.run();
+        vm.run().unwrap();
         assert_eq!(vm.state.stack[0].as_u32(), 1);
 
         let mut vm = init_vm(&vec![PUSH1, 1, PUSH1, 2, DIV], 100);
-        vm.run();
+        vm.run().unwrap();
         assert_eq!(vm.state.stack[0].as_u32(), 2);
 
         let mut vm = init_vm(&vec![PUSH1, 2, PUSH1, 1, GT], 100);
-        vm.run();
+        vm.run().unwrap();
         assert_eq!(vm.state.stack[0].as_u32(), 0);
 
         let mut vm = init_vm(&vec![PUSH1, 2, PUSH1, 1, LT], 100);
-        vm.run();
+        vm.run().unwrap();
         assert_eq!(vm.state.stack[0].as_u32(), 1);
 
         // store 123 at 200, then load it back
-        // 7 words = 200 / 32
+        // 8 words = ceil(232 / 32)
         let mut vm = init_vm(&vec![PUSH1, 123, PUSH1, 200, MSTORE, PUSH1, 200, MLOAD], 100);
-        vm.run();
+        vm.run().unwrap();
         assert_eq!(vm.state.stack[0].as_u32(), 123);
-        assert_eq!(vm.state.active_words.as_u32(), 7);
+        assert_eq!(vm.state.memory.words(), 8);
 
         let mut vm = init_vm(&vec![PUSH1, 123, PUSH1, 231, MSTORE8, PUSH1, 200, MLOAD], 100);
-        vm.run();
+        vm.run().unwrap();
         assert_eq!(vm.state.stack[0].as_u32(), 123);
-        assert_eq!(vm.state.active_words.as_u32(), 7);
+        assert_eq!(vm.state.memory.words(), 8);
 
-        let mut vm = init_vm(&vec![PUSH1, 20, JUMP, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, PUSH1, 123], 100);
-        vm.run();
+        let mut vm = init_vm(&vec![PUSH1, 20, JUMP, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, JUMPDEST, PUSH1, 123], 100);
+        vm.run().unwrap();
         assert_eq!(vm.state.stack[0].as_u32(), 123);
 
         // Div by zero
         let mut vm = init_vm(&vec![PUSH1, 0, PUSH1, 1, DIV], 100);
-        vm.run();
+        vm.run().unwrap();
         assert_eq!(vm.state.stack[0].as_u32(), 0);
     }
+
+    #[test]
+    fn gas_metering() {
+        // two W_verylow pushes and a W_verylow add
+        let mut vm = init_vm(&vec![PUSH1, 1, PUSH1, 2, ADD], 100);
+        vm.run().unwrap();
+        assert_eq!(vm.state.gas_available.as_u32(), 91);
+        assert_eq!(vm.result, Some(VMResult::VmSuccess));
+
+        // GAS reports what's left after paying for itself
+        let mut vm = init_vm(&vec![PUSH1, 0, POP, GAS], 100);
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0].as_u32(), 93);
+
+        // 2 ** 256 has a two-byte exponent: 10 + 2 * 50
+        let mut vm = init_vm(&vec![PUSH2, 1, 0, PUSH1, 2, EXP], 200);
+        vm.run().unwrap();
+        assert_eq!(vm.state.gas_available.as_u32(), 200 - 3 - 3 - 110);
+
+        // a zero exponent only pays G_exp
+        let mut vm = init_vm(&vec![PUSH1, 0, PUSH1, 2, EXP], 100);
+        vm.run().unwrap();
+        assert_eq!(vm.state.gas_available.as_u32(), 100 - 3 - 3 - 10);
+    }
+
+    #[test]
+    fn memory_expansion() {
+        // 8 words of memory cost 3 * 8 + 8 * 8 / 512 = 24 on top of MSTORE's 3
+        let mut vm = init_vm(&vec![PUSH1, 123, PUSH1, 200, MSTORE, MSIZE], 100);
+        vm.run().unwrap();
+        assert_eq!(vm.state.gas_available.as_u32(), 100 - 3 - 3 - 3 - 24 - 2);
+        assert_eq!(vm.state.stack[0].as_u32(), 256);
+
+        // touching already-active memory is free
+        let mut vm = init_vm(&vec![PUSH1, 0, MLOAD, PUSH1, 0, MLOAD], 100);
+        vm.run().unwrap();
+        assert_eq!(vm.state.gas_available.as_u32(), 100 - 3 - 6 - 3 - 3);
+
+        // an offset that doesn't fit in 32 bits is out of gas, not a truncated store
+        let mut vm = init_vm(&vec![PUSH1, 1, PUSH5, 1, 0, 0, 0, 0, MSTORE], 1_000_000);
+        assert_eq!(vm.run(), Err(VmError::OutOfGas));
+        assert!(vm.state.memory.is_empty());
+    }
+
+    #[test]
+    fn out_of_gas() {
+        let mut vm = init_vm(&vec![PUSH1, 1, PUSH1, 2, ADD], 8);
+        assert_eq!(vm.run(), Err(VmError::OutOfGas));
+        assert_eq!(vm.result, Some(VMResult::VmFailure(VmError::OutOfGas)));
+        assert!(vm.state.gas_available.is_zero());
+        // the add never happened
+        assert_eq!(vm.state.stack[0].as_u32(), 2);
+    }
+
+    #[test]
+    fn exceptional_halts() {
+        let mut vm = init_vm(&vec![PUSH1, 1, ADD], 100);
+        assert_eq!(vm.run(), Err(VmError::StackUnderflow));
+        assert_eq!(vm.result, Some(VMResult::VmFailure(VmError::StackUnderflow)));
+        assert!(vm.state.gas_available.is_zero());
+
+        let mut vm = init_vm(&vec![PUSH1, 1, 0xef], 100);
+        assert_eq!(vm.run(), Err(VmError::InvalidOpcode(0xef)));
+        assert!(vm.state.gas_available.is_zero());
+
+        // JUMPDEST DUP1 DUP1 ... loops until the stack is full
+        let mut vm = init_vm(&vec![PUSH1, 0, JUMPDEST, DUP1, PUSH1, 2, JUMP], 1_000_000);
+        assert_eq!(vm.run(), Err(VmError::StackOverflow));
+        assert_eq!(vm.state.stack.len(), STACK_LIMIT);
+    }
+
+    #[test]
+    fn jump_validation() {
+        // into PUSH data that happens to look like a JUMPDEST
+        let mut vm = init_vm(&vec![PUSH1, 3, JUMP, PUSH1, JUMPDEST], 100);
+        assert_eq!(vm.run(), Err(VmError::InvalidJump));
+
+        // past the end of the code
+        let mut vm = init_vm(&vec![PUSH1, 100, JUMP], 100);
+        assert_eq!(vm.run(), Err(VmError::InvalidJump));
+
+        // a JUMPI that isn't taken doesn't care where it would have gone
+        let mut vm = init_vm(&vec![PUSH1, 0, PUSH1, 100, JUMPI, PUSH1, 1], 100);
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0].as_u32(), 1);
+
+        let mut vm = init_vm(&vec![PUSH1, 1, PUSH1, 100, JUMPI], 100);
+        assert_eq!(vm.run(), Err(VmError::InvalidJump));
+
+        let mut vm = init_vm(&vec![PUSH1, 1, PUSH1, 6, JUMPI, STOP, JUMPDEST, PUSH1, 7], 100);
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0].as_u32(), 7);
+    }
+
+    #[test]
+    fn sha3() {
+        let mut vm = init_vm(&vec![PUSH1, 0, PUSH1, 0, SHA3], 100);
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0], hex_u256("c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"));
+        assert_eq!(vm.state.gas_available.as_u32(), 100 - 3 - 3 - 30);
+        assert!(vm.state.memory.is_empty());
+
+        // one word of zeros: 30 + 6 for the word, plus 3 to expand memory
+        let mut vm = init_vm(&vec![PUSH1, 32, PUSH1, 0, SHA3], 100);
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0], hex_u256("290decd9548b62a8d60345a988386fc84ba6bc95484008f6362f93160ef3e563"));
+        assert_eq!(vm.state.gas_available.as_u32(), 100 - 3 - 3 - 36 - 3);
+
+        let mut vm = init_vm(&vec![PUSH1, 0xff, PUSH1, 31, MSTORE8, PUSH1, 32, PUSH1, 0, SHA3], 100);
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0], keccak(&[&[0; 31][..], &[0xff]].concat()));
+    }
+
+    /// Step until the next instruction to execute is `op`
+    fn run_until(vm: &mut VM, op: u8) {
+        while vm.state.code.get(vm.state.pc) != Some(op) {
+            assert_eq!(vm.step(), Ok(InstructionResult::Normal));
+        }
+    }
+
+    #[test]
+    fn calldata() {
+        let data: Vec<u8> = (1..=40).collect();
+
+        let mut vm = init_vm(&vec![PUSH1, 4, CALLDATALOAD, CALLDATASIZE], 100);
+        vm.env.data = data.clone();
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0].as_u32(), 40);
+        assert_eq!(vm.state.stack[1], U256::from(&data[4..36]));
+
+        // reads past the end are padded with zeros
+        let mut vm = init_vm(&vec![PUSH1, 36, CALLDATALOAD], 100);
+        vm.env.data = data.clone();
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0], U256::from(&[&data[36..], &[0; 28][..]].concat()[..]));
+
+        let mut vm = init_vm(&vec![PUSH32, 0xff, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, CALLDATALOAD], 100);
+        vm.env.data = data.clone();
+        vm.run().unwrap();
+        assert!(vm.state.stack[0].is_zero());
+
+        // copy 8 bytes from offset 36 to memory offset 1: 4 bytes of data then zeros
+        let mut vm = init_vm(&vec![PUSH1, 8, PUSH1, 36, PUSH1, 1, CALLDATACOPY], 100);
+        vm.env.data = data.clone();
+        vm.run().unwrap();
+        assert_eq!(vm.state.memory.slice(0, 10), &[0, 37, 38, 39, 40, 0, 0, 0, 0, 0]);
+        // 3 pushes, G_verylow + G_copy for one word, and one word of memory
+        assert_eq!(vm.state.gas_available.as_u32(), 100 - 9 - 6 - 3);
+
+        // a zero-length copy doesn't touch memory, however far away
+        let mut vm = init_vm(&vec![PUSH1, 0, PUSH1, 0, PUSH4, 0xff, 0xff, 0xff, 0xff, CALLDATACOPY], 100);
+        vm.run().unwrap();
+        assert!(vm.state.memory.is_empty());
+    }
+
+    #[test]
+    fn code_and_gas_price() {
+        let code = vec![CODESIZE, PUSH1, 4, PUSH1, 2, PUSH1, 0, CODECOPY, GASPRICE];
+        let mut vm = init_vm(&code, 100);
+        vm.env.gas_price = U256::from(20);
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0].as_u32(), 20);
+        assert_eq!(vm.state.stack[1].as_u32(), 9);
+        assert_eq!(vm.state.memory.slice(0, 4), &code[2..6]);
+
+        // copying past the end of the code pads with zeros (STOPs)
+        let mut vm = init_vm(&vec![PUSH1, 4, PUSH1, 6, PUSH1, 0, CODECOPY], 100);
+        vm.run().unwrap();
+        assert_eq!(vm.state.memory.slice(0, 4), &[CODECOPY, 0, 0, 0]);
+    }
+
+    fn fixture_code(hex: &str) -> Vec<u8> {
+        HEXLOWER.decode(hex.trim().as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn solc_simple_storage() {
+        let creation = fixture_code(include_str!("../tests/fixtures/SimpleStorage.bin"));
+
+        // the constructor copies the runtime code into memory and returns it
+        let mut vm = init_vm(&creation, 100_000);
+        vm.run().unwrap();
+        let runtime = vm.output().to_vec();
+        assert_eq!(runtime.len(), 0xc6);
+        assert_eq!(&runtime[..], &creation[0x1b..0x1b + 0xc6]);
+
+        // set(42) gets through the function dispatcher and decodes its argument from calldata.
+        let mut vm = init_vm(&runtime, 100_000);
+        vm.env.data = fixture_code("60fe47b1000000000000000000000000000000000000000000000000000000000000002a");
+        run_until(&mut vm, SSTORE);
+        assert!(vm.state.stack[0].is_zero());
+        assert_eq!(vm.state.stack[1].as_u32(), 42);
+        vm.run().unwrap();
+        assert_eq!(vm.world.storage(&vm.env.owner, U256::zero()).as_u32(), 42);
+
+        // and get() reads it back
+        let world = mem::replace(&mut vm.world, Box::new(InMemoryState::new()));
+        let mut vm = init_vm(&runtime, 100_000);
+        vm.world = world;
+        vm.env.data = fixture_code("6d4ce63c");
+        vm.run().unwrap();
+        assert_eq!(vm.output(), &fixture_code("000000000000000000000000000000000000000000000000000000000000002a")[..]);
+
+        // an unknown selector falls through to INVALID
+        let mut vm = init_vm(&runtime, 100_000);
+        vm.env.data = fixture_code("deadbeef");
+        assert_eq!(vm.run(), Err(VmError::InvalidOpcode(INVALID)));
+    }
+
+    #[test]
+    fn account_queries() {
+        let mut world = InMemoryState::new();
+        world.insert_account(Address([0xaa; 20]), U256::from(1000), 0, vec![PUSH1, 42, STOP]);
+        world.insert_account(Address([0xbb; 20]), U256::zero(), 0, vec![]);
+
+        let mut code = vec![PUSH20];
+        code.extend_from_slice(&[0xaa; 20]);
+        code.extend_from_slice(&[DUP1, BALANCE, DUP2, EXTCODESIZE, DUP3, EXTCODEHASH]);
+        code.extend_from_slice(&[PUSH1, 4, PUSH1, 1, PUSH1, 0, DUP7, EXTCODECOPY]);
+        let mut vm = init_vm(&code, 10_000);
+        vm.world = Box::new(world.clone());
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0], keccak(&[PUSH1, 42, STOP]));
+        assert_eq!(vm.state.stack[1].as_u32(), 3);
+        assert_eq!(vm.state.stack[2].as_u32(), 1000);
+        assert_eq!(vm.state.memory.slice(0, 4), &[42, STOP, 0, 0]);
+
+        // an empty account and a missing account both hash to zero
+        for addr in [[0xbb; 20], [0xcc; 20]].iter() {
+            let mut code = vec![PUSH20];
+            code.extend_from_slice(addr);
+            code.extend_from_slice(&[DUP1, EXTCODEHASH, SWAP1, BALANCE]);
+            let mut vm = init_vm(&code, 10_000);
+            vm.world = Box::new(world.clone());
+            vm.run().unwrap();
+            assert!(vm.state.stack[0].is_zero());
+            assert!(vm.state.stack[1].is_zero());
+        }
+    }
+
+    /// Run `PUSH1 value PUSH1 0 SSTORE` for each of `values` against an account whose slot 0
+    /// starts out as `original`, returning the gas used and the refund counter
+    fn sstore_gas(original: u8, values: &[u8]) -> (u32, i64) {
+        sstore_gas_under(Hardfork::Istanbul, original, values)
+    }
+
+    fn sstore_gas_under(fork: Hardfork, original: u8, values: &[u8]) -> (u32, i64) {
+        let mut code = vec![];
+        for value in values {
+            code.extend_from_slice(&[PUSH1, *value, PUSH1, 0, SSTORE]);
+        }
+        let mut vm = init_vm(&code, 100_000);
+        vm.set_fork(fork);
+        let mut world = InMemoryState::new();
+        world.insert_storage(vm.env.owner.clone(), U256::zero(), U256::from(original));
+        vm.world = Box::new(world);
+        vm.run().unwrap();
+        (100_000 - vm.state.gas_available.as_u32(), vm.substate.refund)
+    }
+
+    #[test]
+    fn sstore_net_gas_metering() {
+        // the test cases from EIP-2200
+        assert_eq!(sstore_gas(0, &[0, 0]), (1612, 0));
+        assert_eq!(sstore_gas(0, &[0, 1]), (20812, 0));
+        assert_eq!(sstore_gas(0, &[1, 0]), (20812, 19200));
+        assert_eq!(sstore_gas(0, &[1, 2]), (20812, 0));
+        assert_eq!(sstore_gas(0, &[1, 1]), (20812, 0));
+        assert_eq!(sstore_gas(1, &[0, 0]), (5812, 15000));
+        assert_eq!(sstore_gas(1, &[0, 1]), (5812, 4200));
+        assert_eq!(sstore_gas(1, &[0, 2]), (5812, 0));
+        assert_eq!(sstore_gas(1, &[2, 0]), (5812, 15000));
+        assert_eq!(sstore_gas(1, &[2, 3]), (5812, 0));
+        assert_eq!(sstore_gas(1, &[2, 1]), (5812, 4200));
+        assert_eq!(sstore_gas(1, &[1, 0]), (5812, 15000));
+        assert_eq!(sstore_gas(1, &[1, 2]), (5812, 0));
+        assert_eq!(sstore_gas(1, &[1, 1]), (1612, 0));
+        assert_eq!(sstore_gas(0, &[1, 0, 1]), (40818, 19200));
+        assert_eq!(sstore_gas(1, &[0, 1, 0]), (10818, 19200));
+
+        // the sentry: SSTORE needs more than 2300 gas left, even when it would cost less
+        let mut vm = init_vm(&vec![PUSH1, 0, PUSH1, 0, SSTORE], 2306);
+        assert_eq!(vm.run(), Err(VmError::OutOfGas));
+        let mut vm = init_vm(&vec![PUSH1, 0, PUSH1, 0, SSTORE], 2307);
+        vm.run().unwrap();
+    }
+
+    #[test]
+    fn storage_is_reverted_on_exceptional_halt() {
+        let mut vm = init_vm(&vec![PUSH1, 1, PUSH1, 0, SSTORE, PUSH1, 2, PUSH1, 1, SSTORE, INVALID_OPCODE], 100_000);
+        assert_eq!(vm.run(), Err(VmError::InvalidOpcode(INVALID_OPCODE)));
+        assert!(vm.world.storage(&vm.env.owner, U256::zero()).is_zero());
+        assert!(vm.world.storage(&vm.env.owner, U256::one()).is_zero());
+        assert_eq!(vm.substate.refund, 0);
+    }
+
+    #[test]
+    fn logs() {
+        let mut code = vec![PUSH1, 0xab, PUSH1, 0, MSTORE8, PUSH1, 7, PUSH1, 1, PUSH1, 0, LOG0];
+        code.extend_from_slice(&[PUSH1, 3, PUSH1, 2, PUSH1, 1, PUSH1, 2, PUSH1, 0, LOG3]);
+        let mut vm = init_vm(&code, 100_000);
+        vm.env.owner = Address([0xaa; 20]);
+        vm.run().unwrap();
+        // 5 pushes and an MSTORE8 (with a word of memory), LOG0 of one byte, then 5 pushes and
+        // LOG3 of two bytes
+        let used = 15 + 3 + 3 + 375 + 8;
+        let used = used + 15 + 375 + 3 * 375 + 2 * 8;
+        assert_eq!(vm.state.gas_available.as_u32(), 100_000 - used);
+
+        let logs = vm.logs();
+        assert_eq!(logs.len(), 2);
+        assert_eq!(logs[0].address, Address([0xaa; 20]));
+        assert_eq!(logs[0].data, vec![0xab]);
+        assert!(logs[0].topics.is_empty());
+        assert_eq!(logs[1].data, vec![0xab, 0]);
+        assert_eq!(logs[1].topics, vec![K256(U256::from(1)), K256(U256::from(2)), K256(U256::from(3))]);
+        assert_eq!(logs[1].index, 1);
+
+        // logs are discarded along with everything else on an exceptional halt
+        let mut vm = init_vm(&vec![PUSH1, 0, PUSH1, 0, LOG0, INVALID_OPCODE], 100_000);
+        assert!(vm.run().is_err());
+        assert!(vm.logs().is_empty());
+    }
+
+    #[test]
+    fn bloom() {
+        let log = Log {
+            address: Address([0xaa; 20]),
+            topics: vec![K256(U256::one())],
+            data: vec![1, 2, 3],
+            block_number: 0,
+            tx_hash: K256(U256::zero()),
+            tx_index: 0,
+            block_hash: K256(U256::zero()),
+            index: 0,
+            removed: false,
+        };
+        let receipt = TransactionReceipt::new(U256::zero(), vec![log]);
+
+        let mut expected = [0u8; 256];
+        // from the address
+        expected[21] = 0x40;
+        expected[23] = 0x40;
+        expected[168] = 0x02;
+        // from the topic
+        expected[61] = 0x04;
+        expected[85] = 0x04;
+        expected[222] = 0x40;
+        assert_eq!(receipt.bloom, Bloom(expected));
+
+        assert!(receipt.bloom.contains(&[0xaa; 20]));
+        assert!(!receipt.bloom.contains(&[0xbb; 20]));
+        assert_eq!(Bloom::from_logs(&[]), Bloom::new());
+    }
+
+    const CALLER_ADDR: [u8; 20] = [0xaa; 20];
+    const CALLEE_ADDR: [u8; 20] = [0xcc; 20];
+
+    /// A VM running `code` as CALLER_ADDR, with 1000 Wei, next to an account at CALLEE_ADDR
+    /// holding `callee_code` and 1 Wei
+    fn init_call_vm(code: &Vec<u8>, callee_code: Vec<u8>) -> VM {
+        let mut vm = init_vm(code, 1_000_000);
+        vm.env.owner = Address(CALLER_ADDR);
+        vm.env.caller = Address([0xee; 20]);
+        vm.env.transaction_value = U256::from(7);
+        let mut world = InMemoryState::new();
+        world.insert_account(Address(CALLER_ADDR), U256::from(1000), 0, code.clone());
+        world.insert_account(Address(CALLEE_ADDR), U256::one(), 0, callee_code);
+        vm.world = Box::new(world);
+        vm
+    }
+
+    /// Code making an `op` call to CALLEE_ADDR with `gas` and `value` (ignored by DELEGATECALL and
+    /// STATICCALL), no input and no space for output
+    fn call_code(op: u8, gas: u32, value: u16) -> Vec<u8> {
+        let mut code = vec![PUSH1, 0, PUSH1, 0, PUSH1, 0, PUSH1, 0];
+        if op == CALL || op == CALLCODE {
+            code.extend_from_slice(&[PUSH2, (value >> 8) as u8, value as u8]);
+        }
+        code.push(PUSH20);
+        code.extend_from_slice(&CALLEE_ADDR);
+        code.push(PUSH4);
+        code.extend_from_slice(&[(gas >> 24) as u8, (gas >> 16) as u8, (gas >> 8) as u8, gas as u8]);
+        code.push(op);
+        code
+    }
+
+    #[test]
+    fn call_contexts() {
+        // store CALLER, CALLVALUE and ADDRESS in slots 0, 1 and 2
+        let callee = vec![CALLER, PUSH1, 0, SSTORE, CALLVALUE, PUSH1, 1, SSTORE, ADDRESS, PUSH1, 2, SSTORE];
+        let caller = Address(CALLER_ADDR);
+        let callee_addr = Address(CALLEE_ADDR);
+        let stored = |vm: &VM, addr: &Address| -> Vec<U256> {
+            (0..3).map(|i| vm.world.storage(addr, U256::from(i))).collect()
+        };
+
+        let mut vm = init_call_vm(&call_code(CALL, 100_000, 5), callee.clone());
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0], U256::one());
+        assert_eq!(stored(&vm, &callee_addr), vec![addr_to_u256(&caller), U256::from(5), addr_to_u256(&callee_addr)]);
+        assert_eq!(vm.world.balance(&caller), U256::from(995));
+        assert_eq!(vm.world.balance(&callee_addr), U256::from(6));
+        // the caller's frame is back in place
+        assert!(vm.frames.is_empty());
+        assert_eq!(vm.env.owner, caller);
+        assert_eq!(vm.env.depth, 0);
+
+        // CALLCODE runs the callee's code against the caller's storage
+        let mut vm = init_call_vm(&call_code(CALLCODE, 100_000, 5), callee.clone());
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0], U256::one());
+        assert_eq!(stored(&vm, &caller), vec![addr_to_u256(&caller), U256::from(5), addr_to_u256(&caller)]);
+        assert_eq!(vm.world.balance(&caller), U256::from(1000));
+
+        // DELEGATECALL also keeps the caller's own caller and value
+        let mut vm = init_call_vm(&call_code(DELEGATECALL, 100_000, 0), callee.clone());
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0], U256::one());
+        assert_eq!(stored(&vm, &caller), vec![addr_to_u256(&Address([0xee; 20])), U256::from(7), addr_to_u256(&caller)]);
+
+        // the callee can't write to storage inside a STATICCALL
+        let mut vm = init_call_vm(&call_code(STATICCALL, 100_000, 0), callee.clone());
+        vm.run().unwrap();
+        assert!(vm.state.stack[0].is_zero());
+        assert_eq!(stored(&vm, &callee_addr), vec![U256::zero(); 3]);
+    }
+
+    #[test]
+    fn code_is_analysed_once() {
+        let callee = vec![PUSH1, 1, POP];
+        let mut code = call_code(CALL, 10_000, 0);
+        code.extend(call_code(CALL, 10_000, 0));
+        let cache = CodeCache::new();
+        let analysed = cache.analyze(K256(keccak(&callee)), callee.clone());
+
+        // both calls, and a second VM sharing the cache, use the analysis already there
+        for _ in 0..2 {
+            let mut vm = init_call_vm(&code, callee.clone());
+            vm.set_code_cache(cache.clone());
+            vm.run().unwrap();
+            assert_eq!(vm.state.stack[0], U256::one());
+            assert_eq!(vm.state.stack[1], U256::one());
+        }
+        assert_eq!(cache.len(), 1);
+        assert!(Rc::ptr_eq(&analysed, &cache.analyze(K256(keccak(&callee)), Vec::new())));
+    }
+
+    /// A read-only world holding a single account, standing in for a backend from outside the
+    /// crate
+    struct OneAccount {
+        address: Address,
+        balance: U256,
+        code: Vec<u8>,
+    }
+
+    impl StateBackend for OneAccount {
+        fn exists(&self, address: &Address) -> bool {
+            *address == self.address
+        }
+
+        fn balance(&self, address: &Address) -> U256 {
+            if self.exists(address) { self.balance } else { U256::zero() }
+        }
+
+        fn nonce(&self, _address: &Address) -> u64 {
+            0
+        }
+
+        fn code(&self, address: &Address) -> Vec<u8> {
+            if self.exists(address) { self.code.clone() } else { Vec::new() }
+        }
+
+        fn code_hash(&self, address: &Address) -> K256 {
+            if self.exists(address) { K256(keccak(&self.code)) } else { K256(U256::zero()) }
+        }
+
+        fn storage(&self, _address: &Address, _key: U256) -> U256 {
+            U256::zero()
+        }
+
+        fn set_storage(&mut self, _address: &Address, _key: U256, _value: U256) {
+            unreachable!("read only")
+        }
+
+        fn set_balance(&mut self, _address: &Address, _balance: U256) {
+            unreachable!("read only")
+        }
+
+        fn set_nonce(&mut self, _address: &Address, _nonce: u64) {
+            unreachable!("read only")
+        }
+
+        fn set_code(&mut self, _address: &Address, _code: Vec<u8>) {
+            unreachable!("read only")
+        }
+
+        fn remove_account(&mut self, _address: &Address) {
+            unreachable!("read only")
+        }
+    }
+
+    #[test]
+    fn custom_backend() {
+        let address = Address([0xaa; 20]);
+        let world = OneAccount { address: address.clone(), balance: U256::from(1234), code: vec![PUSH1, 42, STOP] };
+        let push_address = |code: &mut Vec<u8>| {
+            code.push(PUSH20);
+            code.extend_from_slice(&address.0);
+        };
+        let mut code = vec![PUSH1, 3, PUSH1, 0, PUSH1, 0];
+        push_address(&mut code);
+        code.push(EXTCODECOPY);
+        for &op in &[EXTCODEHASH, EXTCODESIZE, BALANCE] {
+            push_address(&mut code);
+            code.push(op);
+        }
+
+        let env = Env::new(Address([0xbb; 20]), Address([0xcc; 20]), Address([0xcc; 20]), U256::zero(), U256::zero(), Vec::new());
+        let mut vm = VM::new(code, U256::from(100_000), env, Block::default(), Box::new(world));
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0], U256::from(1234));
+        assert_eq!(vm.state.stack[1], U256::from(3));
+        assert_eq!(vm.state.stack[2], keccak(&[PUSH1, 42, STOP]));
+        assert_eq!(vm.state.memory.slice(0, 3), &[PUSH1, 42, STOP]);
+        assert_eq!(vm.world().balance(&Address([0xbb; 20])), U256::zero());
+    }
+
+    #[test]
+    fn callcode_with_value_inside_staticcall() {
+        // the callee sends 1 Wei to another account with `op` inside a STATICCALL, and returns
+        // whether that worked. CALLCODE only sends it to the callee itself, so it's allowed, but
+        // a CALL with value is an exceptional halt of the callee.
+        let staticcall = |op: u8| -> (U256, U256) {
+            let mut callee = vec![PUSH1, 0, PUSH1, 0, PUSH1, 0, PUSH1, 0, PUSH1, 1, PUSH20];
+            callee.extend_from_slice(&[0xdd; 20]);
+            callee.extend_from_slice(&[GAS, op, PUSH1, 0, MSTORE, PUSH1, 32, PUSH1, 0, RETURN]);
+            let mut code = vec![PUSH1, 32, PUSH1, 0, PUSH1, 0, PUSH1, 0, PUSH20];
+            code.extend_from_slice(&CALLEE_ADDR);
+            code.extend_from_slice(&[GAS, STATICCALL]);
+
+            let mut vm = init_call_vm(&code, callee);
+            vm.run().unwrap();
+            (vm.state.stack[0], U256::from(vm.state.memory.slice(0, 32)))
+        };
+        assert_eq!(staticcall(CALLCODE), (U256::one(), U256::one()));
+        assert_eq!(staticcall(CALL), (U256::zero(), U256::zero()));
+    }
+
+    #[test]
+    fn static_calls_are_inherited() {
+        // the callee makes a plain CALL to an account that writes to its storage, inside a
+        // STATICCALL, so the inner write fails but the callee itself succeeds
+        let inner = Address([0xdd; 20]);
+        let mut callee = vec![PUSH1, 0, PUSH1, 0, PUSH1, 0, PUSH1, 0, PUSH1, 0, PUSH20];
+        callee.extend_from_slice(&inner.0);
+        callee.extend_from_slice(&[GAS, CALL, PUSH1, 0, MSTORE, PUSH1, 32, PUSH1, 0, LOG0]);
+
+        let mut vm = init_call_vm(&call_code(CALL, 200_000, 0), callee.clone());
+        let mut world = InMemoryState::new();
+        world.insert_account(Address(CALLEE_ADDR), U256::zero(), 0, callee.clone());
+        world.insert_account(inner.clone(), U256::zero(), 0, vec![PUSH1, 1, PUSH1, 0, SSTORE]);
+        vm.world = Box::new(world);
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0], U256::one());
+        assert_eq!(vm.world.storage(&inner, U256::zero()), U256::one());
+        assert_eq!(vm.logs().len(), 1);
+
+        let mut vm = init_call_vm(&call_code(STATICCALL, 200_000, 0), callee.clone());
+        let mut world = InMemoryState::new();
+        world.insert_account(Address(CALLEE_ADDR), U256::zero(), 0, callee);
+        world.insert_account(inner.clone(), U256::zero(), 0, vec![PUSH1, 1, PUSH1, 0, SSTORE]);
+        vm.world = Box::new(world);
+        vm.run().unwrap();
+        // LOG0 is a write too
+        assert!(vm.state.stack[0].is_zero());
+        assert!(vm.world.storage(&inner, U256::zero()).is_zero());
+        assert!(vm.logs().is_empty());
+    }
+
+    #[test]
+    fn failed_calls_are_reverted() {
+        let callee = vec![PUSH1, 0, PUSH1, 0, LOG0, PUSH1, 1, PUSH1, 0, SSTORE, INVALID_OPCODE];
+        let mut code = call_code(CALL, 50_000, 5);
+        // the caller carries on afterwards
+        code.extend_from_slice(&[PUSH1, 2, PUSH1, 0, SSTORE]);
+        let mut vm = init_call_vm(&code, callee);
+        vm.run().unwrap();
+        assert_eq!(vm.result, Some(VMResult::VmSuccess));
+        assert_eq!(vm.state.stack.len(), 1);
+        assert!(vm.state.stack[0].is_zero());
+        let callee_addr = Address(CALLEE_ADDR);
+        assert!(vm.world.storage(&callee_addr, U256::zero()).is_zero());
+        assert_eq!(vm.world.storage(&Address(CALLER_ADDR), U256::zero()), U256::from(2));
+        assert_eq!(vm.world.balance(&callee_addr), U256::one());
+        assert_eq!(vm.world.balance(&Address(CALLER_ADDR)), U256::from(1000));
+        assert!(vm.logs().is_empty());
+        // seven pushes, the call with value, all of the callee's gas, then two pushes and SSTORE
+        let used = 21 + 9700 + 50_000 + 6 + 20_000;
+        assert_eq!(vm.state.gas_available.as_u32(), 1_000_000 - used);
+    }
+
+    #[test]
+    fn call_gas() {
+        // the stipend comes back unused from a callee with no code
+        let mut vm = init_call_vm(&call_code(CALL, 0, 1), vec![]);
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0], U256::one());
+        assert_eq!(vm.state.gas_available.as_u32(), 1_000_000 - (21 + 9700 - 2300));
+
+        // sending value to an account that doesn't exist creates it
+        let mut vm = init_call_vm(&call_code(CALL, 0, 1), vec![]);
+        vm.world = Box::new(InMemoryState::new());
+        vm.world.set_balance(&Address(CALLER_ADDR), U256::from(1000));
+        vm.run().unwrap();
+        assert_eq!(vm.state.gas_available.as_u32(), 1_000_000 - (21 + 9700 + 25_000 - 2300));
+        assert_eq!(vm.world.balance(&Address(CALLEE_ADDR)), U256::one());
+
+        // the callee gets at most all but one 64th of what's left
+        let mut vm = init_call_vm(&call_code(CALL, 0xffff_ffff, 0), vec![GAS, PUSH1, 0, SSTORE]);
+        vm.run().unwrap();
+        let available = 1_000_000 - 21 - 700;
+        let forwarded = available - available / 64;
+        assert_eq!(vm.world.storage(&Address(CALLEE_ADDR), U256::zero()).as_u32(), forwarded - 2);
+
+        // calls fail without running past the depth limit or without the balance to send
+        let mut vm = init_call_vm(&call_code(CALL, 50_000, 0), vec![PUSH1, 1, PUSH1, 0, SSTORE]);
+        vm.env.depth = CALL_DEPTH_LIMIT;
+        vm.run().unwrap();
+        assert!(vm.state.stack[0].is_zero());
+        assert!(vm.world.storage(&Address(CALLEE_ADDR), U256::zero()).is_zero());
+        assert_eq!(vm.state.gas_available.as_u32(), 1_000_000 - (21 + 700));
+
+        let mut vm = init_call_vm(&call_code(CALL, 50_000, 1001), vec![]);
+        vm.run().unwrap();
+        assert!(vm.state.stack[0].is_zero());
+        assert_eq!(vm.world.balance(&Address(CALLER_ADDR)), U256::from(1000));
+        assert_eq!(vm.state.gas_available.as_u32(), 1_000_000 - (21 + 9700 - 2300));
+    }
+
+    #[test]
+    fn precompile_calls() {
+        // STATICCALL the identity precompile on the word 0x2a, writing its output to the next word
+        let code = |gas: u8| vec![
+            PUSH1, 0x2a, PUSH1, 0, MSTORE,
+            PUSH1, 32, PUSH1, 32, PUSH1, 32, PUSH1, 0, PUSH1, 4, PUSH2, 0, gas, STATICCALL,
+            PUSH1, 32, MLOAD,
+        ];
+        let mut vm = init_vm(&code(18), 1_000_000);
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0], U256::from(0x2a));
+        assert_eq!(vm.state.stack[1], U256::one());
+        assert_eq!(vm.state.gas_available.as_u32(), 1_000_000 - (12 + 18 + 703 + 18 + 6));
+
+        // without enough gas the call fails and uses everything it was given
+        let mut vm = init_vm(&code(17), 1_000_000);
+        vm.run().unwrap();
+        assert!(vm.state.stack[0].is_zero());
+        assert!(vm.state.stack[1].is_zero());
+        assert_eq!(vm.state.gas_available.as_u32(), 1_000_000 - (12 + 18 + 703 + 17 + 6));
+
+        // BLAKE2F only exists from Istanbul: before then the call runs an empty account and
+        // succeeds, after it fails on the empty input
+        let code = vec![PUSH1, 0, PUSH1, 0, PUSH1, 0, PUSH1, 0, PUSH1, 9, GAS, STATICCALL];
+        let mut vm = init_vm(&code, 1_000_000);
+        vm.set_fork(Hardfork::Petersburg);
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0], U256::one());
+        let mut vm = init_vm(&code, 1_000_000);
+        vm.run().unwrap();
+        assert!(vm.state.stack[0].is_zero());
+    }
+
+    #[test]
+    fn point_evaluation_calls() {
+        // copy the call data into memory and STATICCALL point evaluation on it, writing the output
+        // after the input
+        let code = vec![
+            CALLDATASIZE, PUSH1, 0, PUSH1, 0, CALLDATACOPY,
+            PUSH1, 64, PUSH1, 192, PUSH1, 192, PUSH1, 0, PUSH1, 0x0a, GAS, STATICCALL,
+        ];
+        let vectors: serde_json::Value = serde_json::from_str(
+            include_str!("../tests/fixtures/precompiles/pointEvaluation.json")).unwrap();
+        let hex = |field: &str| HEXLOWER.decode(vectors[0][field].as_str().unwrap().as_bytes()).unwrap();
+        let setup = std::path::Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/precompiles/trusted_setup.txt"));
+
+        let mut vm = init_vm(&code, 1_000_000);
+        vm.set_fork(Hardfork::Cancun);
+        vm.set_precompiles(Precompiles::for_fork_with_trusted_setup(Hardfork::Cancun, setup).unwrap());
+        vm.env.data = hex("Input");
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0], U256::one());
+        assert_eq!(vm.state.memory.slice(192, 64), &hex("Expected")[..]);
+        assert!(vm.substate.accessed_addresses.contains(&precompiles::address(0x0a)));
+
+        // without a trusted setup there's only an empty account there
+        let mut vm = init_vm(&code, 1_000_000);
+        vm.set_fork(Hardfork::Cancun);
+        vm.env.data = hex("Input");
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0], U256::one());
+        assert_eq!(vm.state.memory.slice(192, 64), &[0; 64][..]);
+    }
+
+    /// Code storing `init` in memory and running it with `op`, sending `value` Wei. CREATE2 uses
+    /// a salt of 0x5a.
+    fn create_code(op: u8, init: &[u8], value: u8) -> Vec<u8> {
+        let n = init.len();
+        let mut code = vec![PUSH1 + n as u8 - 1];
+        code.extend_from_slice(init);
+        code.extend_from_slice(&[PUSH1, 0, MSTORE]);
+        if op == CREATE2 {
+            code.extend_from_slice(&[PUSH1, 0x5a]);
+        }
+        code.extend_from_slice(&[PUSH1, n as u8, PUSH1, 32 - n as u8, PUSH1, value, op]);
+        code
+    }
+
+    #[test]
+    fn create() {
+        let creator = Address(CALLER_ADDR);
+        // the init code stores its caller and value
+        let init = [CALLER, PUSH1, 0, SSTORE, CALLVALUE, PUSH1, 1, SSTORE];
+        let mut code = create_code(CREATE, &init, 5);
+        code.extend_from_slice(&create_code(CREATE, &init, 0));
+        let mut vm = init_call_vm(&code, vec![]);
+        vm.run().unwrap();
+
+        let first = create_address(&creator, 0);
+        let second = create_address(&creator, 1);
+        assert_eq!(vm.state.stack[1], addr_to_u256(&first));
+        assert_eq!(vm.state.stack[0], addr_to_u256(&second));
+        assert_eq!(vm.world.nonce(&creator), 2);
+        assert_eq!(vm.world.nonce(&first), 1);
+        assert_eq!(vm.world.balance(&first), U256::from(5));
+        assert_eq!(vm.world.balance(&creator), U256::from(995));
+        assert_eq!(vm.world.storage(&first, U256::zero()), addr_to_u256(&creator));
+        assert_eq!(vm.world.storage(&first, U256::one()), U256::from(5));
+        assert!(vm.world.storage(&second, U256::one()).is_zero());
+        assert_eq!(vm.world.code_hash(&first), state::empty_code_hash());
+    }
+
+    #[test]
+    fn create2() {
+        let creator = Address(CALLER_ADDR);
+        let init = [PUSH1, 1, PUSH1, 0, SSTORE];
+        let mut code = create_code(CREATE2, &init, 0);
+        // the same salt and init code again collides with the first account
+        code.extend_from_slice(&create_code(CREATE2, &init, 0));
+        let mut vm = init_call_vm(&code, vec![]);
+        vm.run().unwrap();
+
+        let address = create2_address(&creator, U256::from(0x5a), &init);
+        assert_eq!(vm.state.stack[1], addr_to_u256(&address));
+        assert!(vm.state.stack[0].is_zero());
+        assert_eq!(vm.world.storage(&address, U256::zero()), U256::one());
+        // the nonce goes up even when the creation fails
+        assert_eq!(vm.world.nonce(&creator), 2);
+        assert_eq!(vm.world.nonce(&address), 1);
+    }
+
+    #[test]
+    fn failed_create_is_reverted() {
+        let creator = Address(CALLER_ADDR);
+        let init = [PUSH1, 1, PUSH1, 0, SSTORE, INVALID_OPCODE];
+        let mut vm = init_call_vm(&create_code(CREATE, &init, 5), vec![]);
+        vm.run().unwrap();
+        assert!(vm.state.stack[0].is_zero());
+        let address = create_address(&creator, 0);
+        assert!(!vm.world.exists(&address));
+        assert_eq!(vm.world.balance(&creator), U256::from(1000));
+        assert_eq!(vm.world.nonce(&creator), 1);
+        // the init code's gas is gone: after five pushes and MSTORE (with a word of memory), the
+        // creation took all but one 64th of what was left
+        let available = 1_000_000 - 15 - 6 - 32_000;
+        assert_eq!(vm.state.gas_available.as_u32(), available / 64);
+
+        // a creation with more value than the creator has doesn't run at all
+        let mut vm = init_call_vm(&create_code(CREATE, &init, 0xff), vec![]);
+        vm.world.set_balance(&creator, U256::from(10));
+        vm.run().unwrap();
+        assert!(vm.state.stack[0].is_zero());
+        assert_eq!(vm.world.nonce(&creator), 0);
+        assert_eq!(vm.state.gas_available.as_u32(), 1_000_000 - 15 - 6 - 32_000);
+    }
+
+    #[test]
+    fn return_and_revert() {
+        let mut vm = init_vm(&vec![PUSH1, 42, PUSH1, 0, MSTORE, PUSH1, 2, PUSH1, 30, RETURN, INVALID], 100);
+        vm.run().unwrap();
+        assert_eq!(vm.result, Some(VMResult::VmSuccess));
+        assert_eq!(vm.output(), &[0, 42]);
+        // four pushes and MSTORE with a word of memory
+        assert_eq!(vm.state.gas_available.as_u32(), 100 - 18);
+
+        // REVERT undoes the SSTORE but keeps the gas that's left
+        let code = vec![PUSH1, 1, PUSH1, 0, SSTORE, PUSH1, 42, PUSH1, 0, MSTORE8, PUSH1, 1, PUSH1, 0, REVERT];
+        let mut vm = init_vm(&code, 100_000);
+        assert_eq!(vm.run(), Ok(()));
+        assert_eq!(vm.result, Some(VMResult::VmRevert));
+        assert_eq!(vm.output(), &[42]);
+        assert!(vm.world.storage(&vm.env.owner, U256::zero()).is_zero());
+        assert_eq!(vm.state.gas_available.as_u32(), 100_000 - (6 + 20_000 + 6 + 3 + 3 + 6));
+
+        let mut vm = init_vm(&vec![INVALID], 100);
+        assert_eq!(vm.run(), Err(VmError::InvalidOpcode(INVALID)));
+        assert!(vm.state.gas_available.is_zero());
+    }
+
+    #[test]
+    fn return_data() {
+        // the callee returns the 32-byte word 0xabcd, as much of it as fits in 4 bytes of memory
+        // at 0x20
+        let callee = vec![PUSH2, 0xab, 0xcd, PUSH1, 0, MSTORE, PUSH1, 32, PUSH1, 0, RETURN];
+        let mut code = vec![PUSH1, 4, PUSH1, 0x20, PUSH1, 0, PUSH1, 0, PUSH20];
+        code.extend_from_slice(&CALLEE_ADDR);
+        code.extend_from_slice(&[GAS, STATICCALL, RETURNDATASIZE]);
+        // copy the last two bytes of the return data to 0x40
+        code.extend_from_slice(&[PUSH1, 2, PUSH1, 30, PUSH1, 0x40, RETURNDATACOPY]);
+        let mut vm = init_call_vm(&code, callee);
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0].as_u32(), 32);
+        assert_eq!(vm.state.stack[1], U256::one());
+        assert_eq!(vm.state.memory.slice(0x20, 4), &[0; 4]);
+        assert_eq!(vm.state.memory.slice(0x40, 2), &[0xab, 0xcd]);
+
+        // reading past the end of the return data is an exceptional halt
+        let mut vm = init_vm(&vec![PUSH1, 1, PUSH1, 0, PUSH1, 0, RETURNDATACOPY], 100);
+        assert_eq!(vm.run(), Err(VmError::ReturnDataOutOfBounds));
+        let mut vm = init_vm(&vec![PUSH1, 0, PUSH1, 0, PUSH1, 0, RETURNDATACOPY], 100);
+        assert_eq!(vm.run(), Ok(()));
+
+        // a reverting callee's state changes are undone, but it returns data and unused gas
+        let callee = vec![PUSH1, 1, PUSH1, 0, SSTORE, PUSH1, 7, PUSH1, 0, MSTORE8, PUSH1, 1, PUSH1, 0, REVERT];
+        let mut code = call_code(CALL, 50_000, 0);
+        code.push(RETURNDATASIZE);
+        let mut vm = init_call_vm(&code, callee);
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0], U256::one());
+        assert!(vm.state.stack[1].is_zero());
+        assert_eq!(vm.state.return_data, vec![7]);
+        assert!(vm.world.storage(&Address(CALLEE_ADDR), U256::zero()).is_zero());
+        let callee_used = 6 + 20_000 + 6 + 3 + 3 + 6;
+        // seven pushes, the call and RETURNDATASIZE
+        assert_eq!(vm.state.gas_available.as_u32(), 1_000_000 - (21 + 700 + callee_used + 2));
+    }
+
+    #[test]
+    fn create_deploys_code() {
+        let creator = Address(CALLER_ADDR);
+        // the init code returns the 3 bytes of code PUSH1 42 STOP
+        let init = [PUSH3, PUSH1, 42, STOP, PUSH1, 0, MSTORE, PUSH1, 3, PUSH1, 29, RETURN];
+        let mut vm = init_call_vm(&create_code(CREATE, &init, 0), vec![]);
+        vm.run().unwrap();
+        let address = create_address(&creator, 0);
+        assert_eq!(vm.state.stack[0], addr_to_u256(&address));
+        assert_eq!(vm.world.code(&address), vec![PUSH1, 42, STOP]);
+        assert_eq!(vm.world.code_hash(&address), K256(keccak(&[PUSH1, 42, STOP])));
+        assert!(vm.state.return_data.is_empty());
+        // the creator's five pushes and MSTORE, then the init code's four pushes, MSTORE and the
+        // deposit
+        let used = 21 + 32_000 + 18 + 3 * 200;
+        assert_eq!(vm.state.gas_available.as_u32(), 1_000_000 - used);
+
+        // without enough gas left to pay for the deposit, the creation fails
+        let mut vm = init_call_vm(&create_code(CREATE, &init, 0), vec![]);
+        // the init code gets 611 of the last 620, leaving 593 for a 600 gas deposit
+        vm.state.gas_available = U256::from(21 + 32_000 + 620);
+        vm.run().unwrap();
+        assert!(vm.state.stack[0].is_zero());
+        assert!(!vm.world.exists(&address));
+        assert_eq!(vm.world.nonce(&creator), 1);
+
+        // REVERT in init code leaves its output as return data
+        let init = [PUSH1, 0xee, PUSH1, 0, MSTORE8, PUSH1, 1, PUSH1, 0, REVERT];
+        let mut vm = init_call_vm(&create_code(CREATE, &init, 0), vec![]);
+        vm.run().unwrap();
+        assert!(vm.state.stack[0].is_zero());
+        assert_eq!(vm.state.return_data, vec![0xee]);
+    }
+
+    /// PUSH20 `beneficiary` SELFDESTRUCT
+    fn selfdestruct_code(beneficiary: &Address) -> Vec<u8> {
+        let mut code = vec![PUSH20];
+        code.extend_from_slice(&beneficiary.0);
+        code.push(SELFDESTRUCT);
+        code
+    }
+
+    #[test]
+    fn selfdestruct() {
+        let owner = Address(CALLER_ADDR);
+        let callee = Address(CALLEE_ADDR);
+
+        let mut vm = init_call_vm(&selfdestruct_code(&callee), vec![]);
+        vm.run().unwrap();
+        assert_eq!(vm.result, Some(VMResult::VmSuccess));
+        assert!(vm.selfdestructs().contains(&owner));
+        assert!(!vm.world.exists(&owner));
+        assert_eq!(vm.world.balance(&callee), U256::from(1001));
+        assert_eq!(vm.substate.refund, gas::R_SELFDESTRUCT);
+        assert_eq!(vm.state.gas_available.as_u32(), 1_000_000 - 3 - 5000);
+
+        // sending the balance to an account that doesn't exist creates it
+        let nobody = Address([0xdd; 20]);
+        let mut vm = init_call_vm(&selfdestruct_code(&nobody), vec![]);
+        vm.run().unwrap();
+        assert_eq!(vm.world.balance(&nobody), U256::from(1000));
+        assert_eq!(vm.state.gas_available.as_u32(), 1_000_000 - 3 - 5000 - 25_000);
+
+        // there's no refund from London
+        let mut vm = init_call_vm(&selfdestruct_code(&callee), vec![]);
+        vm.set_fork(Hardfork::London);
+        vm.run().unwrap();
+        assert!(!vm.world.exists(&owner));
+        assert_eq!(vm.substate.refund, 0);
+
+        // a nested SELFDESTRUCT halts its own frame, and the account goes when the transaction ends
+        let mut code = call_code(CALL, 50_000, 0);
+        code.extend_from_slice(&[PUSH1, 1, PUSH1, 0, SSTORE]);
+        let mut vm = init_call_vm(&code, selfdestruct_code(&owner));
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0], U256::one());
+        assert!(!vm.world.exists(&callee));
+        assert_eq!(vm.world.balance(&owner), U256::from(1001));
+        assert_eq!(vm.world.storage(&owner, U256::zero()), U256::one());
+
+        // it's a state change, so not allowed in a STATICCALL
+        let mut vm = init_call_vm(&call_code(STATICCALL, 50_000, 0), selfdestruct_code(&owner));
+        vm.run().unwrap();
+        assert!(vm.state.stack[0].is_zero());
+        assert!(vm.world.exists(&callee));
+        assert!(vm.selfdestructs().is_empty());
+    }
+
+    #[test]
+    fn selfdestruct_eip_6780() {
+        let owner = Address(CALLER_ADDR);
+        let callee = Address(CALLEE_ADDR);
+
+        // an account that already existed only loses its balance
+        let mut vm = init_call_vm(&selfdestruct_code(&callee), vec![]);
+        vm.set_fork(Hardfork::Cancun);
+        vm.run().unwrap();
+        assert!(vm.selfdestructs().is_empty());
+        assert!(vm.world.exists(&owner));
+        assert!(vm.world.balance(&owner).is_zero());
+        assert_eq!(vm.world.balance(&callee), U256::from(1001));
+
+        // but one created in the same transaction is deleted
+        let mut vm = init_call_vm(&create_code(CREATE, &selfdestruct_code(&callee), 5), vec![]);
+        vm.set_fork(Hardfork::Cancun);
+        vm.run().unwrap();
+        let created = create_address(&owner, 0);
+        assert_eq!(vm.state.stack[0], addr_to_u256(&created));
+        assert!(vm.selfdestructs().contains(&created));
+        assert!(!vm.world.exists(&created));
+        assert_eq!(vm.world.balance(&callee), U256::from(6));
+        assert_eq!(vm.world.balance(&owner), U256::from(995));
+    }
+
+    #[test]
+    fn no_panics() {
+        // arithmetic wraps rather than overflowing
+        let mut vm = init_vm(&vec![PUSH1, 1, PUSH1, 0, SUB, PUSH1, 1, ADD], 100);
+        vm.run().unwrap();
+        assert!(vm.state.stack[0].is_zero());
+
+        // DUP1 duplicates the top of the stack
+        let mut vm = init_vm(&vec![PUSH1, 1, PUSH1, 2, DUP1], 100);
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0].as_u32(), 2);
+        assert_eq!(vm.state.stack[1].as_u32(), 2);
+
+        // BYTE counts from the most significant byte and is 0 past the end
+        let mut vm = init_vm(&vec![PUSH2, 0xab, 0xcd, PUSH1, 30, BYTE], 100);
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0].as_u32(), 0xab);
+        let mut vm = init_vm(&vec![PUSH2, 0xab, 0xcd, PUSH2, 1, 0, BYTE], 100);
+        vm.run().unwrap();
+        assert!(vm.state.stack[0].is_zero());
+
+        // truncated push data is padded with zeros
+        let mut vm = init_vm(&vec![PUSH2, 0xab], 100);
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0].as_u32(), 0xab00);
+    }
+
+    /// Run `op` on `value` shifted by `shift` under Constantinople
+    fn shift(op: u8, shift: u16, value: U256) -> U256 {
+        let mut bytes = [0; 32];
+        value.to_big_endian(&mut bytes);
+        let mut code = vec![PUSH32];
+        code.extend_from_slice(&bytes);
+        code.extend_from_slice(&[PUSH2, (shift >> 8) as u8, shift as u8, op]);
+        let mut vm = init_vm(&code, 100);
+        vm.set_fork(Hardfork::Constantinople);
+        vm.run().unwrap();
+        vm.state.stack[0]
+    }
+
+    #[test]
+    fn shifts() {
+        // the test cases from EIP-145
+        let ones = !U256::zero();
+        let high = U256::one() << 255;
+        let max = ones >> 1;
+        assert_eq!(shift(SHL, 1, U256::one()), U256::from(2));
+        assert_eq!(shift(SHL, 0xff, U256::one()), high);
+        assert_eq!(shift(SHL, 0x100, U256::one()), U256::zero());
+        assert_eq!(shift(SHL, 1, ones), ones - U256::one());
+        assert_eq!(shift(SHL, 0, ones), ones);
+
+        assert_eq!(shift(SHR, 1, high), U256::one() << 254);
+        assert_eq!(shift(SHR, 0xff, high), U256::one());
+        assert_eq!(shift(SHR, 0x100, high), U256::zero());
+        assert_eq!(shift(SHR, 0x101, ones), U256::zero());
+
+        assert_eq!(shift(SAR, 1, high), U256::from(3) << 254);
+        assert_eq!(shift(SAR, 0xff, high), ones);
+        assert_eq!(shift(SAR, 0x100, high), ones);
+        assert_eq!(shift(SAR, 0, ones), ones);
+        assert_eq!(shift(SAR, 0x101, ones), ones);
+        assert_eq!(shift(SAR, 0xfe, U256::one() << 254), U256::one());
+        assert_eq!(shift(SAR, 0xf8, max), U256::from(0x7f));
+        assert_eq!(shift(SAR, 0xfe, max), U256::one());
+        assert_eq!(shift(SAR, 0x100, max), U256::zero());
+
+        let mut vm = init_vm(&vec![PUSH1, 1, PUSH1, 1, SHL], 100);
+        vm.set_fork(Hardfork::Byzantium);
+        assert_eq!(vm.run(), Err(VmError::InvalidOpcode(SHL)));
+    }
+
+    #[test]
+    fn block_opcodes() {
+        let mut vm = init_vm(&vec![CHAINID], 100);
+        vm.set_fork(Hardfork::Petersburg);
+        assert_eq!(vm.run(), Err(VmError::InvalidOpcode(CHAINID)));
+
+        let mut vm = init_vm(&vec![CHAINID, SELFBALANCE], 100);
+        vm.block.chain_id = U256::from(5);
+        vm.world.set_balance(&vm.env.owner.clone(), U256::from(1234));
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[1], U256::from(5));
+        assert_eq!(vm.state.stack[0], U256::from(1234));
+        assert_eq!(vm.state.gas_available.as_u32(), 100 - 2 - 5);
+
+        let mut vm = init_vm(&vec![BASEFEE], 100);
+        vm.set_fork(Hardfork::Berlin);
+        assert_eq!(vm.run(), Err(VmError::InvalidOpcode(BASEFEE)));
+        let mut vm = init_vm(&vec![BASEFEE], 100);
+        vm.set_fork(Hardfork::London);
+        vm.block.base_fee = U256::from(7);
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0], U256::from(7));
+
+        let mut vm = init_vm(&vec![PUSH0], 100);
+        vm.set_fork(Hardfork::London);
+        assert_eq!(vm.run(), Err(VmError::InvalidOpcode(PUSH0)));
+        let mut vm = init_vm(&vec![PUSH1, 1, PUSH0], 100);
+        vm.set_fork(Hardfork::Shanghai);
+        vm.run().unwrap();
+        assert!(vm.state.stack[0].is_zero());
+        assert_eq!(vm.state.gas_available.as_u32(), 100 - 3 - 2);
+    }
+
+    #[test]
+    fn transient_storage() {
+        let mut vm = init_vm(&vec![PUSH1, 7, PUSH1, 1, TSTORE, PUSH1, 1, TLOAD, PUSH1, 2, TLOAD], 100_000);
+        vm.set_fork(Hardfork::Cancun);
+        vm.run().unwrap();
+        assert!(vm.state.stack[0].is_zero());
+        assert_eq!(vm.state.stack[1], U256::from(7));
+        assert_eq!(vm.state.gas_available.as_u32(), 100_000 - (6 + 100 + 3 + 100 + 3 + 100));
+        // it's gone once the transaction completes, and never touched storage
+        assert!(vm.journal.transient_storage(&vm.env.owner, U256::one()).is_zero());
+        assert!(vm.world.storage(&vm.env.owner, U256::one()).is_zero());
+
+        // writes are undone when the call that made them fails
+        let mut code = call_code(DELEGATECALL, 50_000, 0);
+        code.extend_from_slice(&[PUSH1, 0, TLOAD]);
+        let tstore = vec![PUSH1, 1, PUSH1, 0, TSTORE];
+        let mut vm = init_call_vm(&code, tstore.clone());
+        vm.set_fork(Hardfork::Cancun);
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0], U256::one());
+        let mut reverted = tstore.clone();
+        reverted.extend_from_slice(&[PUSH1, 0, PUSH1, 0, REVERT]);
+        let mut vm = init_call_vm(&code, reverted);
+        vm.set_fork(Hardfork::Cancun);
+        vm.run().unwrap();
+        assert!(vm.state.stack[0].is_zero());
+
+        // and not allowed in a STATICCALL
+        let mut vm = init_call_vm(&call_code(STATICCALL, 50_000, 0), tstore);
+        vm.set_fork(Hardfork::Cancun);
+        vm.run().unwrap();
+        assert!(vm.state.stack[0].is_zero());
+
+        let mut vm = init_vm(&vec![PUSH1, 0, TLOAD], 100_000);
+        vm.set_fork(Hardfork::Shanghai);
+        assert_eq!(vm.run(), Err(VmError::InvalidOpcode(TLOAD)));
+    }
+
+    #[test]
+    fn mcopy() {
+        let code = vec![
+            PUSH1, 0x2a, PUSH1, 32, MSTORE,
+            PUSH1, 32, PUSH1, 32, PUSH1, 0, MCOPY,
+            PUSH1, 0, MLOAD,
+        ];
+        let mut vm = init_vm(&code, 100_000);
+        vm.set_fork(Hardfork::Cancun);
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0], U256::from(0x2a));
+        // the MSTORE pays for two words of memory and MCOPY copies one
+        assert_eq!(vm.state.gas_available.as_u32(), 100_000 - (6 + 3 + 6 + 9 + 3 + 3 + 6));
+
+        // copying past the end of memory expands it
+        let mut vm = init_vm(&vec![PUSH1, 1, PUSH1, 64, PUSH1, 0, MCOPY, MSIZE], 100_000);
+        vm.set_fork(Hardfork::Cancun);
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0], U256::from(96));
+    }
+
+    #[test]
+    fn blobs() {
+        let mut vm = init_vm(&vec![PUSH1, 1, BLOBHASH, PUSH1, 0, BLOBHASH, BLOBBASEFEE], 100_000);
+        vm.set_fork(Hardfork::Cancun);
+        vm.env.blob_hashes = vec![K256(U256::from(5))];
+        vm.block.blob_base_fee = U256::from(9);
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0], U256::from(9));
+        assert_eq!(vm.state.stack[1], U256::from(5));
+        assert!(vm.state.stack[2].is_zero());
+
+        let mut vm = init_vm(&vec![BLOBBASEFEE], 100_000);
+        vm.set_fork(Hardfork::Shanghai);
+        assert_eq!(vm.run(), Err(VmError::InvalidOpcode(BLOBBASEFEE)));
+    }
+
+    #[test]
+    fn blockhash() {
+        let code = vec![PUSH2, 1, 44, BLOCKHASH, PUSH1, 43, BLOCKHASH, PUSH1, 44, BLOCKHASH,
+                        PUSH2, 1, 43, BLOCKHASH];
+        let mut hashes = InMemoryBlockHashes::new();
+        for number in 0..300 {
+            hashes.insert(number, K256(U256::from(number + 1000)));
+        }
+        let mut vm = init_vm(&code, 100_000);
+        vm.block.number = U256::from(300);
+        vm.set_block_hashes(Box::new(hashes));
+        vm.run().unwrap();
+        // the block before this one and the oldest of the 256 are there, but not this block or
+        // anything older
+        assert_eq!(vm.state.stack[0], U256::from(1299));
+        assert_eq!(vm.state.stack[1], U256::from(1044));
+        assert!(vm.state.stack[2].is_zero());
+        assert!(vm.state.stack[3].is_zero());
+        assert_eq!(vm.state.gas_available.as_u32(), 100_000 - 4 * (3 + 20));
+
+        // from Prague the hashes can come from the history contract
+        let mut vm = init_vm(&vec![PUSH1, 99, BLOCKHASH], 100_000);
+        vm.set_fork(Hardfork::Prague);
+        vm.block.number = U256::from(100);
+        vm.world.set_storage(&blockhash::HISTORY_STORAGE_ADDRESS, U256::from(99), U256::from(0xabcd));
+        vm.set_block_hashes(Box::new(blockhash::HistoryContract));
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0], U256::from(0xabcd));
+    }
+
+    #[test]
+    fn addmod_mulmod() {
+        // the sum and the product don't wrap before the modulo is taken
+        let mut max = vec![PUSH32];
+        max.extend_from_slice(&[0xff; 32]);
+        let mut code = vec![PUSH1, 3];
+        code.extend_from_slice(&max);
+        code.extend_from_slice(&max);
+        code.push(ADDMOD);
+        code.extend_from_slice(&[PUSH1, 12]);
+        code.extend_from_slice(&max);
+        code.extend_from_slice(&max);
+        code.push(MULMOD);
+        let mut vm = init_vm(&code, 100);
+        vm.run().unwrap();
+        // 2 * (2^256 - 1) mod 3 and (2^256 - 1)^2 mod 12
+        assert_eq!(vm.state.stack[1], U256::zero());
+        assert_eq!(vm.state.stack[0], U256::from(9));
+    }
+
+    /// Run the binary `op` on `a` and `b`, with `a` on top of the stack
+    fn signed(op: u8, a: i64, b: i64) -> I256 {
+        let mut code = vec![];
+        for n in &[b, a] {
+            let mut bytes = [0; 32];
+            U256::from(I256::from(*n)).to_big_endian(&mut bytes);
+            code.push(PUSH32);
+            code.extend_from_slice(&bytes);
+        }
+        code.push(op);
+        let mut vm = init_vm(&code, 100);
+        vm.run().unwrap();
+        I256::from(vm.state.stack[0])
+    }
+
+    #[test]
+    fn signed_arithmetic() {
+        assert_eq!(signed(SDIV, -7, 2), I256::from(-3));
+        assert_eq!(signed(SDIV, 7, 0), I256::zero());
+        assert_eq!(signed(SMOD, -7, 2), I256::from(-1));
+        assert_eq!(signed(SMOD, 7, -2), I256::from(1));
+        assert_eq!(signed(SLT, -2, -1), I256::one());
+        assert_eq!(signed(SLT, -1, -1), I256::zero());
+        assert_eq!(signed(SGT, 1, -1), I256::one());
+        assert_eq!(signed(SGT, -2, -1), I256::zero());
+        assert_eq!(signed(SIGNEXTEND, 0, 0xff), I256::minus_one());
+        assert_eq!(signed(SIGNEXTEND, 0, 0x17f), I256::from(0x7f));
+        assert_eq!(signed(SIGNEXTEND, 31, -5), I256::from(-5));
+        assert_eq!(signed(SIGNEXTEND, 40, 0x1ff), I256::from(0x1ff));
+
+        // MIN / -1 overflows back to MIN
+        let mut min = [0; 32];
+        min[0] = 0x80;
+        let mut code = vec![PUSH1, 1, PUSH1, 0, SUB, PUSH32];
+        code.extend_from_slice(&min);
+        code.push(SDIV);
+        let mut vm = init_vm(&code, 100);
+        vm.run().unwrap();
+        assert_eq!(I256::from(vm.state.stack[0]), I256::min_value());
+    }
+
+    #[test]
+    fn assembled_program() {
+        // jump targets come from labels rather than counted offsets
+        let program = asm::assemble("
+                    PUSH 3
+            loop:   JUMPDEST
+                    PUSH 1 SWAP1 SUB
+                    DUP1 PUSH loop JUMPI
+                    PUSH 123
+        ").unwrap();
+        let mut vm = init_vm(&program.code, 1000);
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0], U256::from(123));
+        assert!(vm.state.stack[1].is_zero());
+    }
+
+    /// The gas used running `code` under `fork`
+    fn gas_under(fork: Hardfork, code: Vec<u8>) -> u32 {
+        let mut vm = init_vm(&code, 100_000);
+        vm.set_fork(fork);
+        vm.run().unwrap();
+        100_000 - vm.state.gas_available.as_u32()
+    }
+
+    #[test]
+    fn fork_opcodes() {
+        let delegatecall = call_code(DELEGATECALL, 0, 0);
+        let mut vm = init_call_vm(&delegatecall, vec![]);
+        vm.set_fork(Hardfork::Frontier);
+        assert_eq!(vm.run(), Err(VmError::InvalidOpcode(DELEGATECALL)));
+        let mut vm = init_call_vm(&delegatecall, vec![]);
+        vm.set_fork(Hardfork::Homestead);
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0], U256::one());
+
+        let revert = vec![PUSH1, 0, PUSH1, 0, REVERT];
+        let mut vm = init_vm(&revert, 100_000);
+        vm.set_fork(Hardfork::SpuriousDragon);
+        assert_eq!(vm.run(), Err(VmError::InvalidOpcode(REVERT)));
+        let mut vm = init_vm(&revert, 100_000);
+        vm.set_fork(Hardfork::Byzantium);
+        vm.run().unwrap();
+        assert_eq!(vm.result, Some(VMResult::VmRevert));
+
+        let mut vm = init_call_vm(&create_code(CREATE2, &[STOP], 0), vec![]);
+        vm.set_fork(Hardfork::Byzantium);
+        assert_eq!(vm.run(), Err(VmError::InvalidOpcode(CREATE2)));
+    }
+
+    #[test]
+    fn fork_gas() {
+        let sload = vec![PUSH1, 0, SLOAD, PUSH1, 0, SLOAD];
+        assert_eq!(gas_under(Hardfork::Frontier, sload.clone()), 3 + 50 + 3 + 50);
+        assert_eq!(gas_under(Hardfork::TangerineWhistle, sload.clone()), 3 + 200 + 3 + 200);
+        assert_eq!(gas_under(Hardfork::Istanbul, sload.clone()), 3 + 800 + 3 + 800);
+        // from Berlin the first access to a slot is cold and the rest are warm
+        assert_eq!(gas_under(Hardfork::Berlin, sload), 3 + 2100 + 3 + 100);
+
+        let balance = vec![PUSH1, 0xbb, BALANCE, PUSH1, 0xbb, BALANCE];
+        assert_eq!(gas_under(Hardfork::Frontier, balance.clone()), 3 + 20 + 3 + 20);
+        assert_eq!(gas_under(Hardfork::TangerineWhistle, balance.clone()), 3 + 400 + 3 + 400);
+        assert_eq!(gas_under(Hardfork::Istanbul, balance.clone()), 3 + 700 + 3 + 700);
+        assert_eq!(gas_under(Hardfork::Berlin, balance), 3 + 2600 + 3 + 100);
+        // the account being run and the precompiles start out warm
+        assert_eq!(gas_under(Hardfork::Berlin, vec![ADDRESS, BALANCE, PUSH1, 1, BALANCE]), 2 + 100 + 3 + 100);
+
+        let exp = vec![PUSH2, 1, 0, PUSH1, 2, EXP];
+        assert_eq!(gas_under(Hardfork::TangerineWhistle, exp.clone()), 3 + 3 + 10 + 2 * 10);
+        assert_eq!(gas_under(Hardfork::SpuriousDragon, exp), 3 + 3 + 10 + 2 * 50);
+
+        // before Tangerine Whistle a callee gets exactly the gas asked for
+        let mut vm = init_call_vm(&call_code(CALL, 0xffff_ffff, 0), vec![]);
+        vm.set_fork(Hardfork::Homestead);
+        assert_eq!(vm.run(), Err(VmError::OutOfGas));
+    }
+
+    #[test]
+    fn fork_sstore() {
+        // no net gas metering before Constantinople or in Petersburg
+        assert_eq!(sstore_gas_under(Hardfork::Petersburg, 0, &[1, 0]), (12 + 20_000 + 5000, 15_000));
+        assert_eq!(sstore_gas_under(Hardfork::Petersburg, 1, &[1]), (6 + 5000, 0));
+        assert_eq!(sstore_gas_under(Hardfork::Constantinople, 0, &[1, 0]), (12 + 20_000 + 200, 19_800));
+        assert_eq!(sstore_gas_under(Hardfork::Berlin, 0, &[1, 0]), (12 + 2100 + 20_000 + 100, 19_900));
+        assert_eq!(sstore_gas_under(Hardfork::Berlin, 1, &[0]), (6 + 2100 + 2900, 15_000));
+        assert_eq!(sstore_gas_under(Hardfork::London, 1, &[0]), (6 + 2100 + 2900, 4800));
+        assert_eq!(sstore_gas_under(Hardfork::London, 1, &[0, 1]), (12 + 2100 + 2900 + 100, 2800));
+
+        // the stipend check only came with EIP-2200
+        let mut vm = init_vm(&vec![PUSH1, 1, PUSH1, 0, SSTORE], 20_006);
+        vm.set_fork(Hardfork::Petersburg);
+        vm.run().unwrap();
+        assert!(vm.state.gas_available.is_zero());
+
+        // the refund is capped at a fifth of the gas used from London
+        assert_eq!(gas::max_refund(Hardfork::Berlin, U256::from(50_000)), U256::from(25_000));
+        assert_eq!(gas::max_refund(Hardfork::London, U256::from(50_000)), U256::from(10_000));
+
+        // a transaction clearing a slot gets back the whole counter while it's under the cap, and
+        // the cap otherwise
+        let mut vm = init_vm(&vec![PUSH1, 0, PUSH1, 0, SSTORE], 100_000);
+        vm.set_fork(Hardfork::London);
+        let mut world = InMemoryState::new();
+        world.insert_storage(vm.env.owner.clone(), U256::zero(), U256::one());
+        vm.world = Box::new(world);
+        vm.run().unwrap();
+        let used = U256::from(100_000) - vm.state.gas_available;
+        assert_eq!(used, U256::from(6 + 2100 + 2900));
+        assert_eq!(vm.refund(used), used / U256::from(5));
+        assert_eq!(vm.refund(used + U256::from(21_000)), U256::from(4800));
+        // and nothing if it fails
+        let mut vm = init_vm(&vec![PUSH1, 0, PUSH1, 0, SSTORE, INVALID_OPCODE], 100_000);
+        vm.set_fork(Hardfork::London);
+        let mut world = InMemoryState::new();
+        world.insert_storage(vm.env.owner.clone(), U256::zero(), U256::one());
+        vm.world = Box::new(world);
+        assert!(vm.run().is_err());
+        assert!(vm.refund(U256::from(100_000)).is_zero());
+    }
+
+    #[test]
+    fn fork_create() {
+        let creator = Address(CALLER_ADDR);
+        let created = create_address(&creator, 0);
+
+        // contracts only start with a nonce of one from Spurious Dragon
+        let mut vm = init_call_vm(&create_code(CREATE, &[STOP], 0), vec![]);
+        vm.set_fork(Hardfork::Homestead);
+        vm.run().unwrap();
+        assert_eq!(vm.state.stack[0], addr_to_u256(&created));
+        assert_eq!(vm.world.nonce(&created), 0);
+
+        // from London code can't start with 0xef
+        let init = [PUSH1, 0xef, PUSH1, 0, MSTORE8, PUSH1, 1, PUSH1, 0, RETURN];
+        let mut vm = init_call_vm(&create_code(CREATE, &init, 0), vec![]);
+        vm.set_fork(Hardfork::Berlin);
+        vm.run().unwrap();
+        assert_eq!(vm.world.code(&created), vec![0xef]);
+        let mut vm = init_call_vm(&create_code(CREATE, &init, 0), vec![]);
+        vm.set_fork(Hardfork::London);
+        vm.run().unwrap();
+        assert!(vm.state.stack[0].is_zero());
+        assert!(!vm.world.exists(&created));
+    }
 }
@@ -0,0 +1,164 @@
+/// The machine's volatile memory, μ_m in the Yellow Paper. It's a word-addressed byte array that
+/// starts out empty and grows (in 32-byte words) whenever an instruction touches an address past
+/// its end. Growing it costs gas, which is what `expansion_cost` computes.
+use bigint::uint::U256;
+use gas::{G_MEMORY, G_QUADDIVISOR};
+
+/// We refuse to grow memory past this many bytes. Reaching it would cost far more gas than any
+/// block allows, so anything beyond it is reported as out-of-gas rather than allocated.
+pub const MAX_MEMORY_BYTES: u64 = 0xffff_ffff;
+
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct Memory(Vec<u8>);
+
+/// C_mem(a): the total cost of a memory `a` words long
+fn memory_cost(words: u64) -> U256 {
+    let words = U256::from(words);
+    U256::from(G_MEMORY) * words + words * words / U256::from(G_QUADDIVISOR)
+}
+
+/// The (exclusive) end of the byte range `[offset, offset + len)`, or `None` if it's too large to
+/// ever be paid for.
+fn range_end(offset: U256, len: U256) -> Option<u64> {
+    let (end, overflow) = offset.overflowing_add(len);
+    if overflow || end > U256::from(MAX_MEMORY_BYTES) {
+        None
+    } else {
+        Some(end.low_u64())
+    }
+}
+
+impl Memory {
+    pub fn new() -> Memory {
+        Memory(Vec::new())
+    }
+
+    /// Size in bytes. Always a multiple of 32.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// μ_i, the number of active words
+    pub fn words(&self) -> u64 {
+        (self.0.len() / 32) as u64
+    }
+
+    /// The gas required to make `[offset, offset + len)` addressable, or `None` if the range is
+    /// absurdly large. Touching zero bytes never expands memory, regardless of `offset`.
+    pub fn expansion_cost(&self, offset: U256, len: U256) -> Option<U256> {
+        if len.is_zero() {
+            return Some(U256::zero());
+        }
+        let new_words = range_end(offset, len)?.div_ceil(32);
+        if new_words <= self.words() {
+            Some(U256::zero())
+        } else {
+            Some(memory_cost(new_words) - memory_cost(self.words()))
+        }
+    }
+
+    /// Grow memory to cover `[offset, offset + len)`. The caller is expected to have paid
+    /// `expansion_cost` first, which also guarantees the range fits.
+    pub fn expand(&mut self, offset: U256, len: U256) {
+        if len.is_zero() {
+            return;
+        }
+        let end = range_end(offset, len).expect("memory range was not checked");
+        let new_len = (end.div_ceil(32) * 32) as usize;
+        if new_len > self.0.len() {
+            self.0.resize(new_len, 0);
+        }
+    }
+
+    pub fn load(&self, offset: usize) -> U256 {
+        U256::from_big_endian(&self.0[offset..offset + 32])
+    }
+
+    pub fn store(&mut self, offset: usize, word: U256) {
+        word.to_big_endian(&mut self.0[offset..offset + 32]);
+    }
+
+    pub fn store8(&mut self, offset: usize, byte: u8) {
+        self.0[offset] = byte;
+    }
+
+    pub fn slice(&self, offset: usize, len: usize) -> &[u8] {
+        if len == 0 { &[] } else { &self.0[offset..offset + len] }
+    }
+
+    /// Copy `len` bytes from `src` to `dst`, which may overlap (MCOPY, EIP-5656)
+    pub fn copy_within(&mut self, dst: usize, src: usize, len: usize) {
+        self.0.copy_within(src..src + len, dst);
+    }
+
+    /// Write `len` bytes taken from `data` starting at `data_offset`, padding with zeros wherever
+    /// that runs past the end of `data`. This is the shared behaviour of the *COPY instructions.
+    pub fn copy_padded(&mut self, offset: usize, data: &[u8], data_offset: U256, len: usize) {
+        for i in 0..len {
+            let (src, overflow) = data_offset.overflowing_add(U256::from(i));
+            self.0[offset + i] = if overflow || src >= U256::from(data.len()) {
+                0
+            } else {
+                data[src.low_u64() as usize]
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bigint::uint::U256;
+    use memory::Memory;
+
+    #[test]
+    fn expansion_cost() {
+        let mem = Memory::new();
+        assert_eq!(mem.expansion_cost(U256::from(0), U256::from(32)), Some(U256::from(3)));
+        // 3 * 32 + 32 * 32 / 512
+        assert_eq!(mem.expansion_cost(U256::from(1000), U256::from(24)), Some(U256::from(98)));
+        assert_eq!(mem.expansion_cost(U256::max_value(), U256::zero()), Some(U256::zero()));
+        assert_eq!(mem.expansion_cost(U256::max_value(), U256::one()), None);
+        assert_eq!(mem.expansion_cost(U256::from(1) << 40, U256::from(32)), None);
+    }
+
+    #[test]
+    fn grows_in_words() {
+        let mut mem = Memory::new();
+        mem.expand(U256::from(31), U256::from(2));
+        assert_eq!(mem.len(), 64);
+        assert_eq!(mem.expansion_cost(U256::from(0), U256::from(64)), Some(U256::zero()));
+        // only the difference is charged: C_mem(3) - C_mem(2)
+        assert_eq!(mem.expansion_cost(U256::from(64), U256::from(1)), Some(U256::from(3)));
+
+        mem.store(32, U256::from(0xabcd));
+        assert_eq!(mem.load(32), U256::from(0xabcd));
+        mem.store8(0, 7);
+        assert_eq!(mem.slice(0, 1), &[7]);
+    }
+
+    #[test]
+    fn copy_within() {
+        let mut mem = Memory::new();
+        mem.expand(U256::zero(), U256::from(64));
+        mem.store(0, U256::from(0x0102030405u64));
+        // overlapping copies see the memory as it was before the copy
+        mem.copy_within(28, 27, 5);
+        assert_eq!(mem.slice(27, 6), &[1, 1, 2, 3, 4, 5]);
+        mem.copy_within(0, 28, 0);
+        assert_eq!(mem.slice(0, 1), &[0]);
+    }
+
+    #[test]
+    fn copy_padded() {
+        let mut mem = Memory::new();
+        mem.expand(U256::zero(), U256::from(4));
+        mem.copy_padded(0, &[1, 2, 3], U256::from(1), 4);
+        assert_eq!(mem.slice(0, 4), &[2, 3, 0, 0]);
+        mem.copy_padded(0, &[1, 2, 3], U256::max_value(), 2);
+        assert_eq!(mem.slice(0, 4), &[0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,237 @@
+/// The instruction set as data: one `OpcodeInfo` per defined opcode, giving everything about an
+/// instruction that doesn't depend on the state it runs in. The interpreter's stack checks and
+/// static-call checks, the fixed part of the gas schedule, code analysis and the disassembler
+/// all read this table rather than keeping their own.
+use gas::*;
+use hardfork::Hardfork;
+use hardfork::Hardfork::*;
+use *;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct OpcodeInfo {
+    pub mnemonic: &'static str,
+    /// Bytes of immediate data following the opcode in the code, which only PUSH has
+    pub immediate: usize,
+    /// δ, the number of items taken off the stack
+    pub inputs: usize,
+    /// α, the number of items put on the stack
+    pub outputs: usize,
+    /// The fixed part of the cost, as of the latest fork. The cost of the instructions that read
+    /// other accounts or storage changed over the forks, so `gas::base_cost` works those out.
+    pub gas: u64,
+    /// The fork the instruction was introduced in
+    pub fork: Hardfork,
+    flags: u8,
+}
+
+const TERMINATING: u8 = 1;
+const JUMP_FLAG: u8 = 2;
+const WRITES_STATE: u8 = 4;
+
+impl OpcodeInfo {
+    /// Whether the instruction always ends the frame it's in
+    pub fn is_terminating(&self) -> bool {
+        self.flags & TERMINATING != 0
+    }
+
+    /// Whether the instruction can move the program counter somewhere other than the next
+    /// instruction
+    pub fn is_jump(&self) -> bool {
+        self.flags & JUMP_FLAG != 0
+    }
+
+    /// Whether the instruction always changes the state, so it isn't allowed inside a STATICCALL.
+    /// CALL only does when it sends value, so it isn't one of these.
+    pub fn writes_state(&self) -> bool {
+        self.flags & WRITES_STATE != 0
+    }
+}
+
+const fn op(mnemonic: &'static str, inputs: usize, outputs: usize, gas: u64, fork: Hardfork, flags: u8)
+    -> Option<OpcodeInfo> {
+    Some(OpcodeInfo { mnemonic, immediate: 0, inputs, outputs, gas, fork, flags })
+}
+
+const PUSH_NAMES: [&str; 32] = [
+    "PUSH1", "PUSH2", "PUSH3", "PUSH4", "PUSH5", "PUSH6", "PUSH7", "PUSH8", "PUSH9", "PUSH10",
+    "PUSH11", "PUSH12", "PUSH13", "PUSH14", "PUSH15", "PUSH16", "PUSH17", "PUSH18", "PUSH19",
+    "PUSH20", "PUSH21", "PUSH22", "PUSH23", "PUSH24", "PUSH25", "PUSH26", "PUSH27", "PUSH28",
+    "PUSH29", "PUSH30", "PUSH31", "PUSH32"
+];
+const DUP_NAMES: [&str; 16] = [
+    "DUP1", "DUP2", "DUP3", "DUP4", "DUP5", "DUP6", "DUP7", "DUP8", "DUP9", "DUP10", "DUP11",
+    "DUP12", "DUP13", "DUP14", "DUP15", "DUP16"
+];
+const SWAP_NAMES: [&str; 16] = [
+    "SWAP1", "SWAP2", "SWAP3", "SWAP4", "SWAP5", "SWAP6", "SWAP7", "SWAP8", "SWAP9", "SWAP10",
+    "SWAP11", "SWAP12", "SWAP13", "SWAP14", "SWAP15", "SWAP16"
+];
+const LOG_NAMES: [&str; 5] = ["LOG0", "LOG1", "LOG2", "LOG3", "LOG4"];
+
+const NONE: Option<OpcodeInfo> = None;
+
+static OPCODES: [Option<OpcodeInfo>; 256] = {
+    let mut table = [NONE; 256];
+
+    table[STOP as usize]       = op("STOP", 0, 0, G_ZERO, Frontier, TERMINATING);
+    table[ADD as usize]        = op("ADD", 2, 1, G_VERYLOW, Frontier, 0);
+    table[MUL as usize]        = op("MUL", 2, 1, G_LOW, Frontier, 0);
+    table[SUB as usize]        = op("SUB", 2, 1, G_VERYLOW, Frontier, 0);
+    table[DIV as usize]        = op("DIV", 2, 1, G_LOW, Frontier, 0);
+    table[SDIV as usize]       = op("SDIV", 2, 1, G_LOW, Frontier, 0);
+    table[MOD as usize]        = op("MOD", 2, 1, G_LOW, Frontier, 0);
+    table[SMOD as usize]       = op("SMOD", 2, 1, G_LOW, Frontier, 0);
+    table[ADDMOD as usize]     = op("ADDMOD", 3, 1, G_MID, Frontier, 0);
+    table[MULMOD as usize]     = op("MULMOD", 3, 1, G_MID, Frontier, 0);
+    table[EXP as usize]        = op("EXP", 2, 1, G_EXP, Frontier, 0);
+    table[SIGNEXTEND as usize] = op("SIGNEXTEND", 2, 1, G_LOW, Frontier, 0);
+
+    table[LT as usize]     = op("LT", 2, 1, G_VERYLOW, Frontier, 0);
+    table[GT as usize]     = op("GT", 2, 1, G_VERYLOW, Frontier, 0);
+    table[SLT as usize]    = op("SLT", 2, 1, G_VERYLOW, Frontier, 0);
+    table[SGT as usize]    = op("SGT", 2, 1, G_VERYLOW, Frontier, 0);
+    table[EQ as usize]     = op("EQ", 2, 1, G_VERYLOW, Frontier, 0);
+    table[ISZERO as usize] = op("ISZERO", 1, 1, G_VERYLOW, Frontier, 0);
+    table[AND as usize]    = op("AND", 2, 1, G_VERYLOW, Frontier, 0);
+    table[OR as usize]     = op("OR", 2, 1, G_VERYLOW, Frontier, 0);
+    table[XOR as usize]    = op("XOR", 2, 1, G_VERYLOW, Frontier, 0);
+    table[NOT as usize]    = op("NOT", 1, 1, G_VERYLOW, Frontier, 0);
+    table[BYTE as usize]   = op("BYTE", 2, 1, G_VERYLOW, Frontier, 0);
+    table[SHL as usize]    = op("SHL", 2, 1, G_VERYLOW, Constantinople, 0);
+    table[SHR as usize]    = op("SHR", 2, 1, G_VERYLOW, Constantinople, 0);
+    table[SAR as usize]    = op("SAR", 2, 1, G_VERYLOW, Constantinople, 0);
+
+    table[SHA3 as usize] = op("SHA3", 2, 1, G_SHA3, Frontier, 0);
+
+    table[ADDRESS as usize]        = op("ADDRESS", 0, 1, G_BASE, Frontier, 0);
+    table[BALANCE as usize]        = op("BALANCE", 1, 1, G_WARMACCESS, Frontier, 0);
+    table[ORIGIN as usize]         = op("ORIGIN", 0, 1, G_BASE, Frontier, 0);
+    table[CALLER as usize]         = op("CALLER", 0, 1, G_BASE, Frontier, 0);
+    table[CALLVALUE as usize]      = op("CALLVALUE", 0, 1, G_BASE, Frontier, 0);
+    table[CALLDATALOAD as usize]   = op("CALLDATALOAD", 1, 1, G_VERYLOW, Frontier, 0);
+    table[CALLDATASIZE as usize]   = op("CALLDATASIZE", 0, 1, G_BASE, Frontier, 0);
+    table[CALLDATACOPY as usize]   = op("CALLDATACOPY", 3, 0, G_VERYLOW, Frontier, 0);
+    table[CODESIZE as usize]       = op("CODESIZE", 0, 1, G_BASE, Frontier, 0);
+    table[CODECOPY as usize]       = op("CODECOPY", 3, 0, G_VERYLOW, Frontier, 0);
+    table[GASPRICE as usize]       = op("GASPRICE", 0, 1, G_BASE, Frontier, 0);
+    table[EXTCODESIZE as usize]    = op("EXTCODESIZE", 1, 1, G_WARMACCESS, Frontier, 0);
+    table[EXTCODECOPY as usize]    = op("EXTCODECOPY", 4, 0, G_WARMACCESS, Frontier, 0);
+    table[RETURNDATASIZE as usize] = op("RETURNDATASIZE", 0, 1, G_BASE, Byzantium, 0);
+    table[RETURNDATACOPY as usize] = op("RETURNDATACOPY", 3, 0, G_VERYLOW, Byzantium, 0);
+    table[EXTCODEHASH as usize]    = op("EXTCODEHASH", 1, 1, G_WARMACCESS, Constantinople, 0);
+
+    table[BLOCKHASH as usize]   = op("BLOCKHASH", 1, 1, G_BLOCKHASH, Frontier, 0);
+    table[COINBASE as usize]    = op("COINBASE", 0, 1, G_BASE, Frontier, 0);
+    table[TIMESTAMP as usize]   = op("TIMESTAMP", 0, 1, G_BASE, Frontier, 0);
+    table[NUMBER as usize]      = op("NUMBER", 0, 1, G_BASE, Frontier, 0);
+    table[DIFFICULTY as usize]  = op("DIFFICULTY", 0, 1, G_BASE, Frontier, 0);
+    table[GASLIMIT as usize]    = op("GASLIMIT", 0, 1, G_BASE, Frontier, 0);
+    table[CHAINID as usize]     = op("CHAINID", 0, 1, G_BASE, Istanbul, 0);
+    table[SELFBALANCE as usize] = op("SELFBALANCE", 0, 1, G_LOW, Istanbul, 0);
+    table[BASEFEE as usize]     = op("BASEFEE", 0, 1, G_BASE, London, 0);
+    table[BLOBHASH as usize]    = op("BLOBHASH", 1, 1, G_VERYLOW, Cancun, 0);
+    table[BLOBBASEFEE as usize] = op("BLOBBASEFEE", 0, 1, G_BASE, Cancun, 0);
+
+    table[POP as usize]      = op("POP", 1, 0, G_BASE, Frontier, 0);
+    table[MLOAD as usize]    = op("MLOAD", 1, 1, G_VERYLOW, Frontier, 0);
+    table[MSTORE as usize]   = op("MSTORE", 2, 0, G_VERYLOW, Frontier, 0);
+    table[MSTORE8 as usize]  = op("MSTORE8", 2, 0, G_VERYLOW, Frontier, 0);
+    table[SLOAD as usize]    = op("SLOAD", 1, 1, G_WARMACCESS, Frontier, 0);
+    // all of SSTORE's cost depends on the slot
+    table[SSTORE as usize]   = op("SSTORE", 2, 0, G_ZERO, Frontier, WRITES_STATE);
+    table[JUMP as usize]     = op("JUMP", 1, 0, G_MID, Frontier, JUMP_FLAG);
+    table[JUMPI as usize]    = op("JUMPI", 2, 0, G_HIGH, Frontier, JUMP_FLAG);
+    table[PC as usize]       = op("PC", 0, 1, G_BASE, Frontier, 0);
+    table[MSIZE as usize]    = op("MSIZE", 0, 1, G_BASE, Frontier, 0);
+    table[GAS as usize]      = op("GAS", 0, 1, G_BASE, Frontier, 0);
+    table[JUMPDEST as usize] = op("JUMPDEST", 0, 0, G_JUMPDEST, Frontier, 0);
+    table[TLOAD as usize]    = op("TLOAD", 1, 1, G_WARMACCESS, Cancun, 0);
+    table[TSTORE as usize]   = op("TSTORE", 2, 0, G_WARMACCESS, Cancun, WRITES_STATE);
+    table[MCOPY as usize]    = op("MCOPY", 3, 0, G_VERYLOW, Cancun, 0);
+    table[PUSH0 as usize]    = op("PUSH0", 0, 1, G_BASE, Shanghai, 0);
+
+    let mut i = 0;
+    while i < 32 {
+        table[PUSH1 as usize + i] = Some(OpcodeInfo {
+            mnemonic: PUSH_NAMES[i], immediate: i + 1, inputs: 0, outputs: 1, gas: G_VERYLOW,
+            fork: Frontier, flags: 0,
+        });
+        i += 1;
+    }
+    let mut i = 0;
+    while i < 16 {
+        // DUPn copies the nth item and SWAPn exchanges the top item with the (n+1)th
+        table[DUP1 as usize + i] = op(DUP_NAMES[i], i + 1, i + 2, G_VERYLOW, Frontier, 0);
+        table[SWAP1 as usize + i] = op(SWAP_NAMES[i], i + 2, i + 2, G_VERYLOW, Frontier, 0);
+        i += 1;
+    }
+    let mut i = 0;
+    while i < 5 {
+        table[LOG0 as usize + i] =
+            op(LOG_NAMES[i], i + 2, 0, G_LOG + G_LOGTOPIC * i as u64, Frontier, WRITES_STATE);
+        i += 1;
+    }
+
+    // the rest of a call's or creation's cost depends on the state and the gas left
+    table[CREATE as usize]       = op("CREATE", 3, 1, G_CREATE, Frontier, WRITES_STATE);
+    table[CALL as usize]         = op("CALL", 7, 1, G_WARMACCESS, Frontier, 0);
+    table[CALLCODE as usize]     = op("CALLCODE", 7, 1, G_WARMACCESS, Frontier, 0);
+    table[RETURN as usize]       = op("RETURN", 2, 0, G_ZERO, Frontier, TERMINATING);
+    table[DELEGATECALL as usize] = op("DELEGATECALL", 6, 1, G_WARMACCESS, Homestead, 0);
+    table[CREATE2 as usize]      = op("CREATE2", 4, 1, G_CREATE, Constantinople, WRITES_STATE);
+    table[STATICCALL as usize]   = op("STATICCALL", 6, 1, G_WARMACCESS, Byzantium, 0);
+    table[REVERT as usize]       = op("REVERT", 2, 0, G_ZERO, Byzantium, TERMINATING);
+    table[INVALID as usize]      = op("INVALID", 0, 0, G_ZERO, Frontier, TERMINATING);
+    table[SELFDESTRUCT as usize] =
+        op("SELFDESTRUCT", 1, 0, G_ZERO, Frontier, TERMINATING | WRITES_STATE);
+
+    table
+};
+
+/// Everything about `op`, or `None` if no fork defines it
+pub fn info(op: u8) -> Option<&'static OpcodeInfo> {
+    OPCODES[op as usize].as_ref()
+}
+
+/// Look an opcode up by its mnemonic
+pub fn from_mnemonic(mnemonic: &str) -> Option<u8> {
+    (0..=255u8).find(|op| info(*op).is_some_and(|info| info.mnemonic == mnemonic))
+}
+
+#[cfg(test)]
+mod tests {
+    use hardfork::Hardfork;
+    use opcodes::{from_mnemonic, info};
+    use *;
+
+    #[test]
+    fn table() {
+        let push2 = info(PUSH2).unwrap();
+        assert_eq!((push2.mnemonic, push2.immediate, push2.inputs, push2.outputs), ("PUSH2", 2, 0, 1));
+        let swap16 = info(SWAP16).unwrap();
+        assert_eq!((swap16.mnemonic, swap16.inputs, swap16.outputs), ("SWAP16", 17, 17));
+        let dup16 = info(DUP16).unwrap();
+        assert_eq!((dup16.mnemonic, dup16.inputs, dup16.outputs), ("DUP16", 16, 17));
+        assert_eq!(info(LOG4).unwrap().gas, 375 * 5);
+        assert_eq!(info(PUSH0).unwrap().fork, Hardfork::Shanghai);
+        assert!(info(0x0c).is_none());
+        assert!(info(0xef).is_none());
+
+        assert!(info(RETURN).unwrap().is_terminating());
+        assert!(!info(CALL).unwrap().is_terminating());
+        assert!(info(JUMPI).unwrap().is_jump());
+        assert!(info(SSTORE).unwrap().writes_state());
+        assert!(!info(SLOAD).unwrap().writes_state());
+    }
+
+    #[test]
+    fn mnemonics() {
+        // every mnemonic names exactly one opcode
+        for op in 0..=255u8 {
+            if let Some(info) = info(op) {
+                assert_eq!(from_mnemonic(info.mnemonic), Some(op), "{}", info.mnemonic);
+            }
+        }
+        assert_eq!(from_mnemonic("KECCAK"), None);
+    }
+}
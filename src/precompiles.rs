@@ -0,0 +1,783 @@
+/// Precompiled contracts (Appendix E of the Yellow Paper): native code that runs in place of EVM
+/// code when one of a handful of reserved addresses is called. The call machinery looks the
+/// address it's calling up in a `Precompiles` registry before it looks for code.
+use std::cmp;
+use std::collections::HashMap;
+use std::path::Path;
+use bigint::uint::U256;
+use c_kzg::{Bytes32, Bytes48, KzgProof, KzgSettings};
+use digest::Digest;
+use libsecp256k1::{recover, Message, RecoveryId, Signature};
+use num::{BigUint, Zero};
+use ripemd160;
+use sha2;
+use substrate_bn::{pairing_batch, AffineG1, AffineG2, Fq, Fq2, Fr, Group, Gt, G1, G2};
+use gas::words;
+use hardfork::Hardfork;
+use {Address, keccak, load_word};
+
+mod bls12_381;
+
+pub use self::bls12_381::{G1Add, G1Msm, G2Add, G2Msm, MapFp2ToG2, MapFpToG1, Pairing};
+
+pub trait Precompile {
+    /// The gas it costs to run on `input`
+    fn gas(&self, input: &[u8]) -> U256;
+
+    /// The output for `input`, or `None` if the input is invalid, which fails the call just like
+    /// an exceptional halt
+    fn execute(&self, input: &[u8]) -> Option<Vec<u8>>;
+}
+
+/// The address of the `n`th precompile, 0x00…0n
+pub fn address(n: u8) -> Address {
+    let mut address = [0u8; 20];
+    address[19] = n;
+    Address(address)
+}
+
+/// The precompiles that exist, keyed by their address
+#[derive(Default)]
+pub struct Precompiles {
+    contracts: HashMap<Address, Box<dyn Precompile>>,
+}
+
+impl Precompiles {
+    pub fn new() -> Precompiles {
+        Precompiles::default()
+    }
+
+    /// The precompiles on mainnet under `fork`'s rules, except for point evaluation, which needs
+    /// a trusted setup (see `for_fork_with_trusted_setup`)
+    pub fn for_fork(fork: Hardfork) -> Precompiles {
+        let mut precompiles = Precompiles::new();
+        precompiles.insert(address(1), Box::new(EcRecover));
+        precompiles.insert(address(2), Box::new(Sha256));
+        precompiles.insert(address(3), Box::new(Ripemd160));
+        precompiles.insert(address(4), Box::new(Identity));
+        if fork >= Hardfork::Byzantium {
+            precompiles.insert(address(5), Box::new(ModExp { eip2565: fork >= Hardfork::Berlin }));
+            let eip1108 = fork >= Hardfork::Istanbul;
+            precompiles.insert(address(6), Box::new(EcAdd { eip1108 }));
+            precompiles.insert(address(7), Box::new(EcMul { eip1108 }));
+            precompiles.insert(address(8), Box::new(EcPairing { eip1108 }));
+        }
+        if fork >= Hardfork::Istanbul {
+            precompiles.insert(address(9), Box::new(Blake2F));
+        }
+        if fork >= Hardfork::Prague {
+            precompiles.insert(address(0x0b), Box::new(G1Add));
+            precompiles.insert(address(0x0c), Box::new(G1Msm));
+            precompiles.insert(address(0x0d), Box::new(G2Add));
+            precompiles.insert(address(0x0e), Box::new(G2Msm));
+            precompiles.insert(address(0x0f), Box::new(Pairing));
+            precompiles.insert(address(0x10), Box::new(MapFpToG1));
+            precompiles.insert(address(0x11), Box::new(MapFp2ToG2));
+        }
+        precompiles
+    }
+
+    /// The precompiles under `fork`'s rules, with the point evaluation precompile from Cancun
+    /// using the KZG trusted setup in the file at `trusted_setup`
+    pub fn for_fork_with_trusted_setup(fork: Hardfork, trusted_setup: &Path) -> Result<Precompiles, c_kzg::Error> {
+        let mut precompiles = Precompiles::for_fork(fork);
+        if fork >= Hardfork::Cancun {
+            precompiles.insert(address(0x0a), Box::new(PointEvaluation::load(trusted_setup)?));
+        }
+        Ok(precompiles)
+    }
+
+    /// Add a precompile, replacing any already at `address`
+    pub fn insert(&mut self, address: Address, precompile: Box<dyn Precompile>) {
+        self.contracts.insert(address, precompile);
+    }
+
+    pub fn get(&self, address: &Address) -> Option<&dyn Precompile> {
+        self.contracts.get(address).map(|precompile| precompile.as_ref())
+    }
+
+    pub fn contains(&self, address: &Address) -> bool {
+        self.contracts.contains_key(address)
+    }
+
+    pub fn addresses(&self) -> impl Iterator<Item = &Address> {
+        self.contracts.keys()
+    }
+}
+
+/// `len` bytes of `input` starting at `offset`, reading zeros past the end
+fn padded(input: &[u8], offset: usize, len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    if offset < input.len() {
+        let available = cmp::min(len, input.len() - offset);
+        bytes[..available].copy_from_slice(&input[offset..offset + available]);
+    }
+    bytes
+}
+
+/// `base` plus `per_word` for every word of `input`, the price of the hashing precompiles
+fn linear_cost(base: u64, per_word: u64, input: &[u8]) -> U256 {
+    U256::from(base).saturating_add(U256::from(per_word).saturating_mul(words(U256::from(input.len()))))
+}
+
+/// 0x01: the address that signed a message hash, from an ECDSA signature on secp256k1
+pub struct EcRecover;
+
+impl Precompile for EcRecover {
+    fn gas(&self, _input: &[u8]) -> U256 {
+        U256::from(3000)
+    }
+
+    /// The input is the hash, v, r and s, each a 32-byte word. A signature that doesn't recover
+    /// to a key isn't an error, it just gives empty output.
+    fn execute(&self, input: &[u8]) -> Option<Vec<u8>> {
+        let input = padded(input, 0, 128);
+        // v is a whole word, either 27 or 28
+        if input[32..63].iter().any(|byte| *byte != 0) || (input[63] != 27 && input[63] != 28) {
+            return Some(Vec::new());
+        }
+
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&input[..32]);
+        let signature = match Signature::parse_standard_slice(&input[64..128]) {
+            Ok(signature) => signature,
+            Err(_) => return Some(Vec::new()),
+        };
+        let recovery_id = RecoveryId::parse(input[63] - 27).expect("v was checked");
+
+        match recover(&Message::parse(&hash), &signature, &recovery_id) {
+            Ok(key) => {
+                // the address is the low 20 bytes of the hash of the key, without its 0x04 prefix
+                let mut output = [0u8; 32];
+                keccak(&key.serialize()[1..]).to_big_endian(&mut output);
+                for byte in output[..12].iter_mut() {
+                    *byte = 0;
+                }
+                Some(output.to_vec())
+            },
+            Err(_) => Some(Vec::new()),
+        }
+    }
+}
+
+/// 0x02: SHA2-256
+pub struct Sha256;
+
+impl Precompile for Sha256 {
+    fn gas(&self, input: &[u8]) -> U256 {
+        linear_cost(60, 12, input)
+    }
+
+    fn execute(&self, input: &[u8]) -> Option<Vec<u8>> {
+        let mut hasher = sha2::Sha256::default();
+        hasher.input(input);
+        Some(hasher.result().to_vec())
+    }
+}
+
+/// 0x03: RIPEMD-160, left-padded with zeros to 32 bytes
+pub struct Ripemd160;
+
+impl Precompile for Ripemd160 {
+    fn gas(&self, input: &[u8]) -> U256 {
+        linear_cost(600, 120, input)
+    }
+
+    fn execute(&self, input: &[u8]) -> Option<Vec<u8>> {
+        use ripemd160::Digest;
+        let mut hasher = ripemd160::Ripemd160::default();
+        hasher.input(input);
+        let mut output = vec![0u8; 12];
+        output.extend_from_slice(&hasher.result());
+        Some(output)
+    }
+}
+
+/// 0x04: the identity function, for copying memory cheaply
+pub struct Identity;
+
+impl Precompile for Identity {
+    fn gas(&self, input: &[u8]) -> U256 {
+        linear_cost(15, 3, input)
+    }
+
+    fn execute(&self, input: &[u8]) -> Option<Vec<u8>> {
+        Some(input.to_vec())
+    }
+}
+
+/// 0x05: modular exponentiation of arbitrarily large numbers (EIP-198), priced by EIP-198 or,
+/// from Berlin, EIP-2565
+pub struct ModExp {
+    pub eip2565: bool,
+}
+
+impl ModExp {
+    /// The lengths of the base, exponent and modulus, which make up the first three words of the
+    /// input
+    fn lengths(input: &[u8]) -> (U256, U256, U256) {
+        (load_word(input, U256::zero()), load_word(input, U256::from(32)), load_word(input, U256::from(64)))
+    }
+
+    /// The number of squarings the exponentiation will take, roughly: the index of the
+    /// exponent's highest set bit. Only the first 32 bytes of the exponent are looked at.
+    fn iteration_count(input: &[u8], base_len: U256, exp_len: U256) -> U256 {
+        let (exp_offset, overflow) = base_len.overflowing_add(U256::from(96));
+        let head = if overflow { U256::zero() } else { load_word(input, exp_offset) };
+        let head = if exp_len < U256::from(32) {
+            head >> (8 * (32 - exp_len.low_u64() as usize))
+        } else {
+            head
+        };
+        let head_bits = U256::from(head.bits().saturating_sub(1));
+
+        if exp_len <= U256::from(32) {
+            head_bits
+        } else {
+            (exp_len - U256::from(32)).saturating_mul(U256::from(8)).saturating_add(head_bits)
+        }
+    }
+
+    /// The multiplication complexity of EIP-198, for numbers of `len` bytes
+    fn eip198_complexity(len: U256) -> U256 {
+        let square = len.saturating_mul(len);
+        if len <= U256::from(64) {
+            square
+        } else if len <= U256::from(1024) {
+            square / U256::from(4) + U256::from(96) * len - U256::from(3072)
+        } else {
+            (square / U256::from(16)).saturating_add(U256::from(480).saturating_mul(len)) - U256::from(199_680)
+        }
+    }
+
+    /// The multiplication complexity of EIP-2565: the square of the number of 64-bit words
+    fn eip2565_complexity(len: U256) -> U256 {
+        let words = len / U256::from(8) + if (len % U256::from(8)).is_zero() { U256::zero() } else { U256::one() };
+        words.saturating_mul(words)
+    }
+}
+
+impl Precompile for ModExp {
+    fn gas(&self, input: &[u8]) -> U256 {
+        let (base_len, exp_len, mod_len) = ModExp::lengths(input);
+        let len = cmp::max(base_len, mod_len);
+        let iterations = cmp::max(ModExp::iteration_count(input, base_len, exp_len), U256::one());
+        if self.eip2565 {
+            let gas = ModExp::eip2565_complexity(len).saturating_mul(iterations) / U256::from(3);
+            cmp::max(gas, U256::from(200))
+        } else {
+            ModExp::eip198_complexity(len).saturating_mul(iterations) / U256::from(20)
+        }
+    }
+
+    /// BASE^EXP % MOD, as many bytes long as MOD. The lengths are bounded by the gas that's
+    /// already been paid, so they fit in a usize.
+    fn execute(&self, input: &[u8]) -> Option<Vec<u8>> {
+        let (base_len, exp_len, mod_len) = ModExp::lengths(input);
+        if mod_len.is_zero() {
+            return Some(Vec::new());
+        }
+        let (base_len, exp_len, mod_len) =
+            (base_len.low_u64() as usize, exp_len.low_u64() as usize, mod_len.low_u64() as usize);
+
+        let base     = BigUint::from_bytes_be(&padded(input, 96, base_len));
+        let exponent = BigUint::from_bytes_be(&padded(input, 96 + base_len, exp_len));
+        let modulus  = BigUint::from_bytes_be(&padded(input, 96 + base_len + exp_len, mod_len));
+
+        let result = if modulus.is_zero() {
+            Vec::new()
+        } else {
+            base.modpow(&exponent, &modulus).to_bytes_be()
+        };
+        // to_bytes_be gives at least one byte, even for zero
+        let result = &result[result.len().saturating_sub(mod_len)..];
+        let mut output = vec![0u8; mod_len - result.len()];
+        output.extend_from_slice(result);
+        Some(output)
+    }
+}
+
+/// A point on alt_bn128's G1 from 64 bytes of input, with (0, 0) standing for the point at
+/// infinity. `None` if a coordinate isn't in the field or the point isn't on the curve.
+fn read_g1(input: &[u8]) -> Option<G1> {
+    let x = Fq::from_slice(&input[..32]).ok()?;
+    let y = Fq::from_slice(&input[32..64]).ok()?;
+    if x.is_zero() && y.is_zero() {
+        Some(G1::zero())
+    } else {
+        AffineG1::new(x, y).ok().map(Into::into)
+    }
+}
+
+/// A point on alt_bn128's G2 from 128 bytes of input. Each coordinate is in F_p^2, encoded
+/// imaginary part first.
+fn read_g2(input: &[u8]) -> Option<G2> {
+    let fq = |i: usize| Fq::from_slice(&input[32 * i..32 * (i + 1)]).ok();
+    let x = Fq2::new(fq(1)?, fq(0)?);
+    let y = Fq2::new(fq(3)?, fq(2)?);
+    if x.is_zero() && y.is_zero() {
+        Some(G2::zero())
+    } else {
+        AffineG2::new(x, y).ok().map(Into::into)
+    }
+}
+
+/// A point on G1 as 64 bytes of output, with the point at infinity as zeros
+fn write_g1(point: G1) -> Vec<u8> {
+    let mut output = vec![0u8; 64];
+    if let Some(point) = AffineG1::from_jacobian(point) {
+        point.x().to_big_endian(&mut output[..32]).expect("32 bytes fit an Fq");
+        point.y().to_big_endian(&mut output[32..]).expect("32 bytes fit an Fq");
+    }
+    output
+}
+
+/// 0x06: point addition on alt_bn128 (EIP-196), repriced by EIP-1108 in Istanbul
+pub struct EcAdd {
+    pub eip1108: bool,
+}
+
+impl Precompile for EcAdd {
+    fn gas(&self, _input: &[u8]) -> U256 {
+        U256::from(if self.eip1108 { 150 } else { 500 })
+    }
+
+    fn execute(&self, input: &[u8]) -> Option<Vec<u8>> {
+        let input = padded(input, 0, 128);
+        Some(write_g1(read_g1(&input[..64])? + read_g1(&input[64..])?))
+    }
+}
+
+/// 0x07: scalar multiplication on alt_bn128 (EIP-196), repriced by EIP-1108 in Istanbul
+pub struct EcMul {
+    pub eip1108: bool,
+}
+
+impl Precompile for EcMul {
+    fn gas(&self, _input: &[u8]) -> U256 {
+        U256::from(if self.eip1108 { 6_000 } else { 40_000 })
+    }
+
+    fn execute(&self, input: &[u8]) -> Option<Vec<u8>> {
+        let input = padded(input, 0, 96);
+        // the scalar is reduced modulo the group order rather than rejected
+        let scalar = Fr::from_slice(&input[64..]).ok()?;
+        Some(write_g1(read_g1(&input[..64])? * scalar))
+    }
+}
+
+/// 0x08: whether the product of the pairings of a list of points on G1 and G2 is one
+/// (EIP-197), repriced by EIP-1108 in Istanbul
+pub struct EcPairing {
+    pub eip1108: bool,
+}
+
+impl Precompile for EcPairing {
+    fn gas(&self, input: &[u8]) -> U256 {
+        let (base, per_pair) = if self.eip1108 { (45_000, 34_000) } else { (100_000, 80_000) };
+        U256::from(base) + U256::from(per_pair) * U256::from(input.len() / 192)
+    }
+
+    fn execute(&self, input: &[u8]) -> Option<Vec<u8>> {
+        if !input.len().is_multiple_of(192) {
+            return None;
+        }
+        let mut pairs = Vec::with_capacity(input.len() / 192);
+        for pair in input.chunks(192) {
+            pairs.push((read_g1(&pair[..64])?, read_g2(&pair[64..])?));
+        }
+
+        let mut output = vec![0u8; 32];
+        if pairs.is_empty() || pairing_batch(&pairs) == Gt::one() {
+            output[31] = 1;
+        }
+        Some(output)
+    }
+}
+
+/// 0x09: the compression function F of BLAKE2b (EIP-152)
+pub struct Blake2F;
+
+/// The message word permutations of BLAKE2b's rounds
+const SIGMA: [[usize; 16]; 10] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+    [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+    [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+    [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+    [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+    [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+    [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+    [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+    [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+];
+
+/// BLAKE2b's initialization vector, the same as SHA-512's
+const IV: [u64; 8] = [
+    0x6a09_e667_f3bc_c908, 0xbb67_ae85_84ca_a73b, 0x3c6e_f372_fe94_f82b, 0xa54f_f53a_5f1d_36f1,
+    0x510e_527f_ade6_82d1, 0x9b05_688c_2b3e_6c1f, 0x1f83_d9ab_fb41_bd6b, 0x5be0_cd19_137e_2179,
+];
+
+impl Blake2F {
+    /// The mixing function G (RFC 7693, section 3.1)
+    fn mix(v: &mut [u64; 16], (a, b, c, d): (usize, usize, usize, usize), x: u64, y: u64) {
+        v[a] = v[a].wrapping_add(v[b]).wrapping_add(x);
+        v[d] = (v[d] ^ v[a]).rotate_right(32);
+        v[c] = v[c].wrapping_add(v[d]);
+        v[b] = (v[b] ^ v[c]).rotate_right(24);
+        v[a] = v[a].wrapping_add(v[b]).wrapping_add(y);
+        v[d] = (v[d] ^ v[a]).rotate_right(16);
+        v[c] = v[c].wrapping_add(v[d]);
+        v[b] = (v[b] ^ v[c]).rotate_right(63);
+    }
+
+    /// The compression function F (RFC 7693, section 3.2), with a variable number of rounds
+    fn compress(rounds: u32, h: &mut [u64; 8], m: &[u64; 16], t: [u64; 2], last: bool) {
+        let mut v = [0u64; 16];
+        v[..8].copy_from_slice(h);
+        v[8..].copy_from_slice(&IV);
+        v[12] ^= t[0];
+        v[13] ^= t[1];
+        if last {
+            v[14] = !v[14];
+        }
+
+        for round in 0..rounds as usize {
+            let s = &SIGMA[round % 10];
+            Blake2F::mix(&mut v, (0, 4, 8, 12), m[s[0]], m[s[1]]);
+            Blake2F::mix(&mut v, (1, 5, 9, 13), m[s[2]], m[s[3]]);
+            Blake2F::mix(&mut v, (2, 6, 10, 14), m[s[4]], m[s[5]]);
+            Blake2F::mix(&mut v, (3, 7, 11, 15), m[s[6]], m[s[7]]);
+            Blake2F::mix(&mut v, (0, 5, 10, 15), m[s[8]], m[s[9]]);
+            Blake2F::mix(&mut v, (1, 6, 11, 12), m[s[10]], m[s[11]]);
+            Blake2F::mix(&mut v, (2, 7, 8, 13), m[s[12]], m[s[13]]);
+            Blake2F::mix(&mut v, (3, 4, 9, 14), m[s[14]], m[s[15]]);
+        }
+
+        for i in 0..8 {
+            h[i] ^= v[i] ^ v[i + 8];
+        }
+    }
+}
+
+/// The little-endian word at `offset`
+fn read_u64_le(input: &[u8], offset: usize) -> u64 {
+    input[offset..offset + 8].iter().rev().fold(0, |word, byte| (word << 8) | u64::from(*byte))
+}
+
+impl Precompile for Blake2F {
+    /// One gas per round. Input of the wrong length is rejected, so it costs nothing.
+    fn gas(&self, input: &[u8]) -> U256 {
+        if input.len() != 213 {
+            return U256::zero();
+        }
+        U256::from(input[..4].iter().fold(0u32, |rounds, byte| (rounds << 8) | u32::from(*byte)))
+    }
+
+    /// The input is exactly 213 bytes: the rounds (4 bytes, big-endian), the state h (8 words),
+    /// the message block m (16 words), the offset counter t (2 words) and the final block flag f
+    /// (1 byte, 0 or 1). Words are little-endian.
+    fn execute(&self, input: &[u8]) -> Option<Vec<u8>> {
+        if input.len() != 213 {
+            return None;
+        }
+        let last = match input[212] {
+            0 => false,
+            1 => true,
+            _ => return None,
+        };
+        let rounds = self.gas(input).low_u32();
+
+        let mut h = [0u64; 8];
+        for (i, word) in h.iter_mut().enumerate() {
+            *word = read_u64_le(input, 4 + 8 * i);
+        }
+        let mut m = [0u64; 16];
+        for (i, word) in m.iter_mut().enumerate() {
+            *word = read_u64_le(input, 68 + 8 * i);
+        }
+        let t = [read_u64_le(input, 196), read_u64_le(input, 204)];
+
+        Blake2F::compress(rounds, &mut h, &m, t, last);
+        let mut output = Vec::with_capacity(64);
+        for word in h.iter() {
+            for i in 0..8 {
+                output.push((word >> (8 * i)) as u8);
+            }
+        }
+        Some(output)
+    }
+}
+
+/// 0x0a: checks a KZG proof that the polynomial committed to by a blob commitment takes the
+/// value y at z (EIP-4844)
+pub struct PointEvaluation {
+    settings: KzgSettings,
+}
+
+/// The version byte of a versioned hash of a KZG commitment
+const VERSIONED_HASH_VERSION_KZG: u8 = 0x01;
+
+impl PointEvaluation {
+    /// Load the trusted setup from a file in the format of the one in c-kzg-4844, as used on
+    /// mainnet
+    pub fn load(trusted_setup: &Path) -> Result<PointEvaluation, c_kzg::Error> {
+        Ok(PointEvaluation { settings: KzgSettings::load_trusted_setup_file(trusted_setup)? })
+    }
+}
+
+impl Precompile for PointEvaluation {
+    fn gas(&self, _input: &[u8]) -> U256 {
+        U256::from(50_000)
+    }
+
+    /// The input is the versioned hash of the commitment, z, y, the commitment and the proof, and
+    /// the output is the number of field elements per blob and the modulus of the field they're
+    /// in, as two words
+    fn execute(&self, input: &[u8]) -> Option<Vec<u8>> {
+        if input.len() != 192 {
+            return None;
+        }
+        let commitment = &input[96..144];
+        let mut hasher = sha2::Sha256::default();
+        hasher.input(commitment);
+        let mut versioned_hash = hasher.result();
+        versioned_hash[0] = VERSIONED_HASH_VERSION_KZG;
+        if versioned_hash[..] != input[..32] {
+            return None;
+        }
+
+        let valid = KzgProof::verify_kzg_proof(
+            &Bytes48::from_bytes(commitment).ok()?,
+            &Bytes32::from_bytes(&input[32..64]).ok()?,
+            &Bytes32::from_bytes(&input[64..96]).ok()?,
+            &Bytes48::from_bytes(&input[144..]).ok()?,
+            &self.settings,
+        ).unwrap_or(false);
+        if !valid {
+            return None;
+        }
+
+        let mut output = vec![0u8; 64];
+        U256::from(c_kzg::FIELD_ELEMENTS_PER_BLOB).to_big_endian(&mut output[..32]);
+        output[32..].copy_from_slice(&BLS_MODULUS);
+        Some(output)
+    }
+}
+
+/// The order of BLS12-381's scalar field, big-endian
+const BLS_MODULUS: [u8; 32] = [
+    0x73, 0xed, 0xa7, 0x53, 0x29, 0x9d, 0x7d, 0x48, 0x33, 0x39, 0xd8, 0x08, 0x09, 0xa1, 0xd8, 0x05,
+    0x53, 0xbd, 0xa4, 0x02, 0xff, 0xfe, 0x5b, 0xfe, 0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x01,
+];
+
+#[cfg(test)]
+mod tests {
+    use bigint::uint::U256;
+    use data_encoding::HEXLOWER;
+    use serde_json;
+    use std::path::Path;
+    use hardfork::Hardfork;
+    use precompiles::*;
+
+    /// A test vector in the format go-ethereum keeps its precompile tests in
+    #[derive(Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    pub struct Vector {
+        pub input: String,
+        pub expected: String,
+        pub name: String,
+        pub gas: u64,
+    }
+
+    fn hex(hex: &str) -> Vec<u8> {
+        HEXLOWER.decode(hex.as_bytes()).unwrap()
+    }
+
+    /// Check `precompile` against every vector in a JSON fixture
+    pub fn check_vectors(precompile: &dyn Precompile, json: &str) {
+        let vectors: Vec<Vector> = serde_json::from_str(json).unwrap();
+        for vector in vectors {
+            let input = hex(&vector.input);
+            assert_eq!(precompile.gas(&input), U256::from(vector.gas), "gas for {}", vector.name);
+            assert_eq!(precompile.execute(&input), Some(hex(&vector.expected)), "output of {}", vector.name);
+        }
+    }
+
+    /// A test vector for input that has to be rejected
+    #[derive(Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    pub struct FailVector {
+        pub input: String,
+        pub expected_error: String,
+        pub name: String,
+    }
+
+    /// Check that `precompile` rejects the input of every vector in a JSON fixture
+    pub fn check_failures(precompile: &dyn Precompile, json: &str) {
+        let vectors: Vec<FailVector> = serde_json::from_str(json).unwrap();
+        for vector in vectors {
+            let input = hex(&vector.input);
+            assert_eq!(precompile.execute(&input), None, "{} should fail: {}", vector.name, vector.expected_error);
+        }
+    }
+
+    #[test]
+    fn ecrecover() {
+        let hash = "741efa311f97686956946758e0d95f70f11ff2da4f2feb7c54314f44134ac49f";
+        let v = "000000000000000000000000000000000000000000000000000000000000001b";
+        let rs = "da2a8932ac2dd0c8bba52a56306caf60378b4eb99e906f02f64c87992f7e25f3\
+                  27dcf3e99bd721e59104f49e75615a974a4e395f264a29e6abd4415c4455afea";
+        let input = hex(&format!("{}{}{}", hash, v, rs.replace(" ", "")));
+        assert_eq!(EcRecover.gas(&input), U256::from(3000));
+        assert_eq!(EcRecover.execute(&input), Some(hex("000000000000000000000000a94f5374fce5edbc8e2a8697c15331677e6ebf0b")));
+
+        // the other v recovers some other key, if any
+        let mut other = input.clone();
+        other[63] = 28;
+        assert_ne!(EcRecover.execute(&other), EcRecover.execute(&input));
+
+        // anything but 27 or 28 is no signature at all
+        let mut bad_v = input.clone();
+        bad_v[63] = 29;
+        assert_eq!(EcRecover.execute(&bad_v), Some(Vec::new()));
+        bad_v[63] = 27;
+        bad_v[62] = 1;
+        assert_eq!(EcRecover.execute(&bad_v), Some(Vec::new()));
+
+        // and neither is an s of zero or an r that overflows the curve order
+        let mut bad_s = input.clone();
+        for byte in bad_s[96..].iter_mut() {
+            *byte = 0;
+        }
+        assert_eq!(EcRecover.execute(&bad_s), Some(Vec::new()));
+        let mut bad_r = input.clone();
+        for byte in bad_r[64..96].iter_mut() {
+            *byte = 0xff;
+        }
+        assert_eq!(EcRecover.execute(&bad_r), Some(Vec::new()));
+    }
+
+    #[test]
+    fn hashes() {
+        assert_eq!(Sha256.execute(b""), Some(hex("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")));
+        assert_eq!(Sha256.execute(b"abc"), Some(hex("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")));
+        assert_eq!(Sha256.gas(b""), U256::from(60));
+        assert_eq!(Sha256.gas(&[0; 33]), U256::from(60 + 2 * 12));
+
+        assert_eq!(Ripemd160.execute(b""), Some(hex("0000000000000000000000009c1185a5c5e9fc54612808977ee8f548b2258d31")));
+        assert_eq!(Ripemd160.execute(b"abc"), Some(hex("0000000000000000000000008eb208f7e05d987a9b044a8e98c6b087f15a0bfc")));
+        assert_eq!(Ripemd160.gas(b"abc"), U256::from(720));
+
+        assert_eq!(Identity.execute(b"abc"), Some(b"abc".to_vec()));
+        assert_eq!(Identity.gas(&[0; 64]), U256::from(21));
+    }
+
+    #[test]
+    fn modexp() {
+        check_vectors(&ModExp { eip2565: false }, include_str!("../tests/fixtures/precompiles/modexp.json"));
+        check_vectors(&ModExp { eip2565: true }, include_str!("../tests/fixtures/precompiles/modexp_eip2565.json"));
+
+        // no input is no output, and a zero modulus gives zeros
+        assert_eq!(ModExp { eip2565: true }.execute(&[]), Some(Vec::new()));
+        assert_eq!(ModExp { eip2565: true }.gas(&[]), U256::from(200));
+        let mut input = vec![0u8; 96];
+        input[31] = 1;
+        input[63] = 1;
+        input[95] = 2;
+        input.extend_from_slice(&[3, 5]);
+        assert_eq!(ModExp { eip2565: true }.execute(&input), Some(vec![0, 0]));
+
+        // absurd lengths cost more gas than there is
+        let mut input = vec![0xff; 96];
+        input[..32].copy_from_slice(&[0; 32]);
+        assert_eq!(ModExp { eip2565: false }.gas(&input), U256::max_value() / U256::from(20));
+    }
+
+    #[test]
+    fn alt_bn128() {
+        check_vectors(&EcAdd { eip1108: true }, include_str!("../tests/fixtures/precompiles/bn256Add.json"));
+        check_vectors(&EcMul { eip1108: true }, include_str!("../tests/fixtures/precompiles/bn256ScalarMul.json"));
+        check_vectors(&EcPairing { eip1108: true }, include_str!("../tests/fixtures/precompiles/bn256Pairing.json"));
+
+        // Byzantium's prices
+        assert_eq!(EcAdd { eip1108: false }.gas(&[]), U256::from(500));
+        assert_eq!(EcMul { eip1108: false }.gas(&[]), U256::from(40_000));
+        assert_eq!(EcPairing { eip1108: false }.gas(&[0; 384]), U256::from(260_000));
+
+        // missing input is zeros, which is the point at infinity
+        assert_eq!(EcAdd { eip1108: true }.execute(&[]), Some(vec![0; 64]));
+        assert_eq!(EcMul { eip1108: true }.execute(&[]), Some(vec![0; 64]));
+
+        // points off the curve, coordinates outside the field and input that isn't a whole
+        // number of pairs are all invalid
+        assert_eq!(EcAdd { eip1108: true }.execute(&[0x11; 128]), None);
+        assert_eq!(EcMul { eip1108: true }.execute(&[0x11; 96]), None);
+        assert_eq!(EcPairing { eip1108: true }.execute(&[0x11; 192]), None);
+        let mut outside = vec![0u8; 64];
+        outside[..32].copy_from_slice(&[0xff; 32]);
+        assert_eq!(EcAdd { eip1108: true }.execute(&outside), None);
+        assert_eq!(EcPairing { eip1108: true }.execute(&[0; 191]), None);
+    }
+
+    #[test]
+    fn blake2f() {
+        check_vectors(&Blake2F, include_str!("../tests/fixtures/precompiles/blake2F.json"));
+
+        let vectors: Vec<Vector> = serde_json::from_str(include_str!("../tests/fixtures/precompiles/blake2F.json")).unwrap();
+        let input = hex(&vectors[0].input);
+        // the input has to be exactly 213 bytes, ending in a flag of 0 or 1
+        assert_eq!(Blake2F.execute(&input[1..]), None);
+        assert_eq!(Blake2F.execute(&[&input[..], &[0]].concat()), None);
+        let mut bad_flag = input.clone();
+        bad_flag[212] = 2;
+        assert_eq!(Blake2F.execute(&bad_flag), None);
+        let mut rounds = input.clone();
+        rounds[..4].copy_from_slice(&[0xff; 4]);
+        assert_eq!(Blake2F.gas(&rounds), U256::from(0xffff_ffffu32));
+    }
+
+    #[test]
+    fn point_evaluation() {
+        let setup = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/precompiles/trusted_setup.txt"));
+        let point_evaluation = PointEvaluation::load(setup).unwrap();
+        check_vectors(&point_evaluation, include_str!("../tests/fixtures/precompiles/pointEvaluation.json"));
+        check_failures(&point_evaluation, include_str!("../tests/fixtures/precompiles/fail-pointEvaluation.json"));
+
+        assert!(PointEvaluation::load(Path::new("/nonexistent/trusted_setup.txt")).is_err());
+        assert!(!Precompiles::for_fork_with_trusted_setup(Hardfork::Shanghai, setup).unwrap().contains(&address(0x0a)));
+        assert!(Precompiles::for_fork_with_trusted_setup(Hardfork::Cancun, setup).unwrap().contains(&address(0x0a)));
+        // without a trusted setup there's no point evaluation at all
+        assert!(!Precompiles::for_fork(Hardfork::Cancun).contains(&address(0x0a)));
+    }
+
+    #[test]
+    fn registry() {
+        let frontier = Precompiles::for_fork(Hardfork::Frontier);
+        assert!(frontier.contains(&address(4)));
+        assert!(!frontier.contains(&address(5)));
+        assert!(frontier.get(&address(0)).is_none());
+
+        let berlin = Precompiles::for_fork(Hardfork::Berlin);
+        let mut input = vec![0u8; 96];
+        input[31] = 1;
+        input[63] = 1;
+        input[95] = 1;
+        assert_eq!(berlin.get(&address(5)).unwrap().gas(&input), U256::from(200));
+        assert_eq!(Precompiles::for_fork(Hardfork::Byzantium).get(&address(5)).unwrap().gas(&input), U256::zero());
+
+        let byzantium = Precompiles::for_fork(Hardfork::Byzantium);
+        assert_eq!(byzantium.get(&address(6)).unwrap().gas(&[]), U256::from(500));
+        assert!(!byzantium.contains(&address(9)));
+        let istanbul = Precompiles::for_fork(Hardfork::Istanbul);
+        assert_eq!(istanbul.get(&address(6)).unwrap().gas(&[]), U256::from(150));
+        assert!(istanbul.contains(&address(9)));
+        assert!(!istanbul.contains(&address(10)));
+
+        let cancun = Precompiles::for_fork(Hardfork::Cancun);
+        assert!(!cancun.contains(&address(0x0b)));
+        let prague = Precompiles::for_fork(Hardfork::Prague);
+        assert!((0x0b..=0x11).all(|n| prague.contains(&address(n))));
+        assert!(!prague.contains(&address(0x12)));
+    }
+}
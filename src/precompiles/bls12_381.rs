@@ -0,0 +1,369 @@
+/// The BLS12-381 precompiles of EIP-2537, from Prague: addition and multi-scalar
+/// multiplication on G1 and G2, the pairing check and mapping field elements to the curve.
+///
+/// Field elements are 64 bytes: a big-endian number below the field modulus, padded with 16
+/// zero bytes at the front. A G1 point is two of them, x and y, and a G2 point four, with each
+/// coordinate in F_p^2 written as c0 then c1. The point at infinity is all zeros. Scalars are
+/// 32 bytes, big-endian, and may be larger than the group order.
+use std::cmp::{self, Ordering};
+use std::ptr;
+use bigint::uint::U256;
+use blst::{
+    blst_bendian_from_fp, blst_final_exp, blst_fp, blst_fp12, blst_fp12_is_one, blst_fp12_mul,
+    blst_fp2, blst_fp_from_bendian, blst_map_to_g1, blst_map_to_g2, blst_miller_loop, blst_p1,
+    blst_p1_add_or_double_affine, blst_p1_affine, blst_p1_affine_in_g1, blst_p1_affine_on_curve,
+    blst_p1_from_affine, blst_p1_to_affine, blst_p2, blst_p2_add_or_double_affine,
+    blst_p2_affine, blst_p2_affine_in_g2, blst_p2_affine_on_curve, blst_p2_from_affine,
+    blst_p2_to_affine, blst_scalar, blst_scalar_from_bendian, p1_affines, p2_affines,
+};
+use precompiles::Precompile;
+
+/// The length of an encoded field element
+const FP_LENGTH: usize = 64;
+const G1_LENGTH: usize = 2 * FP_LENGTH;
+const G2_LENGTH: usize = 4 * FP_LENGTH;
+const SCALAR_LENGTH: usize = 32;
+
+/// The field modulus, big-endian
+const MODULUS: [u8; 48] = [
+    0x1a, 0x01, 0x11, 0xea, 0x39, 0x7f, 0xe6, 0x9a, 0x4b, 0x1b, 0xa7, 0xb6, 0x43, 0x4b, 0xac, 0xd7,
+    0x64, 0x77, 0x4b, 0x84, 0xf3, 0x85, 0x12, 0xbf, 0x67, 0x30, 0xd2, 0xa0, 0xf6, 0xb0, 0xf6, 0x24,
+    0x1e, 0xab, 0xff, 0xfe, 0xb1, 0x53, 0xff, 0xff, 0xb9, 0xfe, 0xff, 0xff, 0xff, 0xff, 0xaa, 0xab,
+];
+
+/// A field element from its 64-byte encoding. `None` unless the padding is zeros and the
+/// number is below the modulus.
+fn read_fp(input: &[u8]) -> Option<blst_fp> {
+    let (padding, bytes) = input[..FP_LENGTH].split_at(FP_LENGTH - MODULUS.len());
+    if padding.iter().any(|byte| *byte != 0) || bytes.cmp(&MODULUS[..]) != Ordering::Less {
+        return None;
+    }
+    let mut fp = blst_fp::default();
+    unsafe { blst_fp_from_bendian(&mut fp, bytes.as_ptr()) };
+    Some(fp)
+}
+
+fn write_fp(output: &mut [u8], fp: &blst_fp) {
+    unsafe { blst_bendian_from_fp(output[FP_LENGTH - MODULUS.len()..FP_LENGTH].as_mut_ptr(), fp) };
+}
+
+fn read_fp2(input: &[u8]) -> Option<blst_fp2> {
+    Some(blst_fp2 { fp: [read_fp(input)?, read_fp(&input[FP_LENGTH..])?] })
+}
+
+/// A point on G1's curve. Multiplication and pairing also need it to be in the subgroup of
+/// prime order, which addition doesn't check.
+fn read_g1(input: &[u8], subgroup_check: bool) -> Option<blst_p1_affine> {
+    let point = blst_p1_affine { x: read_fp(input)?, y: read_fp(&input[FP_LENGTH..])? };
+    let valid = unsafe {
+        if subgroup_check { blst_p1_affine_in_g1(&point) } else { blst_p1_affine_on_curve(&point) }
+    };
+    if valid { Some(point) } else { None }
+}
+
+fn read_g2(input: &[u8], subgroup_check: bool) -> Option<blst_p2_affine> {
+    let point = blst_p2_affine { x: read_fp2(input)?, y: read_fp2(&input[2 * FP_LENGTH..])? };
+    let valid = unsafe {
+        if subgroup_check { blst_p2_affine_in_g2(&point) } else { blst_p2_affine_on_curve(&point) }
+    };
+    if valid { Some(point) } else { None }
+}
+
+fn write_g1(point: &blst_p1) -> Vec<u8> {
+    let mut affine = blst_p1_affine::default();
+    unsafe { blst_p1_to_affine(&mut affine, point) };
+    let mut output = vec![0u8; G1_LENGTH];
+    write_fp(&mut output, &affine.x);
+    write_fp(&mut output[FP_LENGTH..], &affine.y);
+    output
+}
+
+fn write_g2(point: &blst_p2) -> Vec<u8> {
+    let mut affine = blst_p2_affine::default();
+    unsafe { blst_p2_to_affine(&mut affine, point) };
+    let mut output = vec![0u8; G2_LENGTH];
+    for (i, fp) in [affine.x.fp[0], affine.x.fp[1], affine.y.fp[0], affine.y.fp[1]].iter().enumerate() {
+        write_fp(&mut output[i * FP_LENGTH..], fp);
+    }
+    output
+}
+
+/// A scalar's bytes in the little-endian order blst's multiplication takes
+fn read_scalar(input: &[u8]) -> [u8; SCALAR_LENGTH] {
+    let mut scalar = blst_scalar::default();
+    unsafe { blst_scalar_from_bendian(&mut scalar, input.as_ptr()) };
+    scalar.b
+}
+
+/// The discounts, in thousandths, that multi-scalar multiplication gets for `k` pairs, indexed
+/// by `k - 1`. Past the end of the table the last discount applies.
+const G1_MSM_DISCOUNTS: [u64; 128] = [
+    1000, 949, 848, 797, 764, 750, 738, 728, 719, 712, 705, 698, 692, 687, 682, 677, 673, 669, 665,
+    661, 658, 654, 651, 648, 645, 642, 640, 637, 635, 632, 630, 627, 625, 623, 621, 619, 617, 615,
+    613, 611, 609, 608, 606, 604, 603, 601, 599, 598, 596, 595, 593, 592, 591, 589, 588, 586, 585,
+    584, 582, 581, 580, 579, 577, 576, 575, 574, 573, 572, 570, 569, 568, 567, 566, 565, 564, 563,
+    562, 561, 560, 559, 558, 557, 556, 555, 554, 553, 552, 551, 550, 549, 548, 547, 547, 546, 545,
+    544, 543, 542, 541, 540, 540, 539, 538, 537, 536, 536, 535, 534, 533, 532, 532, 531, 530, 529,
+    528, 528, 527, 526, 525, 525, 524, 523, 522, 522, 521, 520, 520, 519,
+];
+
+const G2_MSM_DISCOUNTS: [u64; 128] = [
+    1000, 1000, 923, 884, 855, 832, 812, 796, 782, 770, 759, 749, 740, 732, 724, 717, 711, 704, 699,
+    693, 688, 683, 679, 674, 670, 666, 663, 659, 655, 652, 649, 646, 643, 640, 637, 634, 632, 629,
+    627, 624, 622, 620, 618, 615, 613, 611, 609, 607, 606, 604, 602, 600, 598, 597, 595, 593, 592,
+    590, 589, 587, 586, 584, 583, 582, 580, 579, 578, 576, 575, 574, 573, 571, 570, 569, 568, 567,
+    566, 565, 563, 562, 561, 560, 559, 558, 557, 556, 555, 554, 553, 552, 552, 551, 550, 549, 548,
+    547, 546, 545, 545, 544, 543, 542, 541, 541, 540, 539, 538, 537, 537, 536, 535, 535, 534, 533,
+    532, 532, 531, 530, 530, 529, 528, 528, 527, 526, 526, 525, 524, 524,
+];
+
+/// The price of a multi-scalar multiplication whose (point, scalar) pairs are each
+/// `pair_length` bytes of `input`
+fn msm_cost(input: &[u8], pair_length: usize, multiplication: u64, discounts: &[u64; 128]) -> U256 {
+    let k = input.len() / pair_length;
+    if k == 0 {
+        return U256::zero();
+    }
+    let discount = discounts[cmp::min(k, discounts.len()) - 1];
+    U256::from(k) * U256::from(multiplication) * U256::from(discount) / U256::from(1000)
+}
+
+/// 0x0b: addition on G1
+pub struct G1Add;
+
+impl Precompile for G1Add {
+    fn gas(&self, _input: &[u8]) -> U256 {
+        U256::from(375)
+    }
+
+    fn execute(&self, input: &[u8]) -> Option<Vec<u8>> {
+        if input.len() != 2 * G1_LENGTH {
+            return None;
+        }
+        let a = read_g1(input, false)?;
+        let b = read_g1(&input[G1_LENGTH..], false)?;
+        let (mut projective, mut sum) = (blst_p1::default(), blst_p1::default());
+        unsafe {
+            blst_p1_from_affine(&mut projective, &b);
+            blst_p1_add_or_double_affine(&mut sum, &projective, &a);
+        }
+        Some(write_g1(&sum))
+    }
+}
+
+/// 0x0c: multi-scalar multiplication on G1, the sum of a list of points each multiplied by a
+/// scalar
+pub struct G1Msm;
+
+impl Precompile for G1Msm {
+    fn gas(&self, input: &[u8]) -> U256 {
+        msm_cost(input, G1_LENGTH + SCALAR_LENGTH, 12_000, &G1_MSM_DISCOUNTS)
+    }
+
+    fn execute(&self, input: &[u8]) -> Option<Vec<u8>> {
+        let pair_length = G1_LENGTH + SCALAR_LENGTH;
+        if input.is_empty() || !input.len().is_multiple_of(pair_length) {
+            return None;
+        }
+        let mut points = Vec::new();
+        let mut scalars = Vec::new();
+        for pair in input.chunks(pair_length) {
+            let point = read_g1(pair, true)?;
+            // blst's batch multiplication can't take the point at infinity, which adds nothing
+            if pair[..G1_LENGTH].iter().all(|byte| *byte == 0) {
+                continue;
+            }
+            let mut projective = blst_p1::default();
+            unsafe { blst_p1_from_affine(&mut projective, &point) };
+            points.push(projective);
+            scalars.extend_from_slice(&read_scalar(&pair[G1_LENGTH..]));
+        }
+
+        if points.is_empty() {
+            return Some(vec![0; G1_LENGTH]);
+        }
+        Some(write_g1(&p1_affines::from(&points).mult(&scalars, 8 * SCALAR_LENGTH)))
+    }
+}
+
+/// 0x0d: addition on G2
+pub struct G2Add;
+
+impl Precompile for G2Add {
+    fn gas(&self, _input: &[u8]) -> U256 {
+        U256::from(600)
+    }
+
+    fn execute(&self, input: &[u8]) -> Option<Vec<u8>> {
+        if input.len() != 2 * G2_LENGTH {
+            return None;
+        }
+        let a = read_g2(input, false)?;
+        let b = read_g2(&input[G2_LENGTH..], false)?;
+        let (mut projective, mut sum) = (blst_p2::default(), blst_p2::default());
+        unsafe {
+            blst_p2_from_affine(&mut projective, &b);
+            blst_p2_add_or_double_affine(&mut sum, &projective, &a);
+        }
+        Some(write_g2(&sum))
+    }
+}
+
+/// 0x0e: multi-scalar multiplication on G2
+pub struct G2Msm;
+
+impl Precompile for G2Msm {
+    fn gas(&self, input: &[u8]) -> U256 {
+        msm_cost(input, G2_LENGTH + SCALAR_LENGTH, 22_500, &G2_MSM_DISCOUNTS)
+    }
+
+    fn execute(&self, input: &[u8]) -> Option<Vec<u8>> {
+        let pair_length = G2_LENGTH + SCALAR_LENGTH;
+        if input.is_empty() || !input.len().is_multiple_of(pair_length) {
+            return None;
+        }
+        let mut points = Vec::new();
+        let mut scalars = Vec::new();
+        for pair in input.chunks(pair_length) {
+            let point = read_g2(pair, true)?;
+            if pair[..G2_LENGTH].iter().all(|byte| *byte == 0) {
+                continue;
+            }
+            let mut projective = blst_p2::default();
+            unsafe { blst_p2_from_affine(&mut projective, &point) };
+            points.push(projective);
+            scalars.extend_from_slice(&read_scalar(&pair[G2_LENGTH..]));
+        }
+
+        if points.is_empty() {
+            return Some(vec![0; G2_LENGTH]);
+        }
+        Some(write_g2(&p2_affines::from(&points).mult(&scalars, 8 * SCALAR_LENGTH)))
+    }
+}
+
+/// 0x0f: whether the product of the pairings of a list of points on G1 and G2 is one
+pub struct Pairing;
+
+impl Precompile for Pairing {
+    fn gas(&self, input: &[u8]) -> U256 {
+        U256::from(37_700) + U256::from(32_600) * U256::from(input.len() / (G1_LENGTH + G2_LENGTH))
+    }
+
+    fn execute(&self, input: &[u8]) -> Option<Vec<u8>> {
+        let pair_length = G1_LENGTH + G2_LENGTH;
+        if input.is_empty() || !input.len().is_multiple_of(pair_length) {
+            return None;
+        }
+        let mut product: Option<blst_fp12> = None;
+        for pair in input.chunks(pair_length) {
+            let p = read_g1(pair, true)?;
+            let q = read_g2(&pair[G1_LENGTH..], true)?;
+            let mut miller = blst_fp12::default();
+            unsafe { blst_miller_loop(&mut miller, &q, &p) };
+            product = Some(match product {
+                Some(previous) => {
+                    let mut next = blst_fp12::default();
+                    unsafe { blst_fp12_mul(&mut next, &previous, &miller) };
+                    next
+                },
+                None => miller,
+            });
+        }
+
+        let mut result = blst_fp12::default();
+        let product = product.expect("there's at least one pair");
+        unsafe { blst_final_exp(&mut result, &product) };
+        let mut output = vec![0u8; 32];
+        if unsafe { blst_fp12_is_one(&result) } {
+            output[31] = 1;
+        }
+        Some(output)
+    }
+}
+
+/// 0x10: maps an element of the field onto G1, with the simplified SWU map of RFC 9380
+pub struct MapFpToG1;
+
+impl Precompile for MapFpToG1 {
+    fn gas(&self, _input: &[u8]) -> U256 {
+        U256::from(5_500)
+    }
+
+    fn execute(&self, input: &[u8]) -> Option<Vec<u8>> {
+        if input.len() != FP_LENGTH {
+            return None;
+        }
+        let fp = read_fp(input)?;
+        let mut point = blst_p1::default();
+        unsafe { blst_map_to_g1(&mut point, &fp, ptr::null()) };
+        Some(write_g1(&point))
+    }
+}
+
+/// 0x11: maps an element of F_p^2 onto G2
+pub struct MapFp2ToG2;
+
+impl Precompile for MapFp2ToG2 {
+    fn gas(&self, _input: &[u8]) -> U256 {
+        U256::from(23_800)
+    }
+
+    fn execute(&self, input: &[u8]) -> Option<Vec<u8>> {
+        if input.len() != 2 * FP_LENGTH {
+            return None;
+        }
+        let fp2 = read_fp2(input)?;
+        let mut point = blst_p2::default();
+        unsafe { blst_map_to_g2(&mut point, &fp2, ptr::null()) };
+        Some(write_g2(&point))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bigint::uint::U256;
+    use precompiles::Precompile;
+    use precompiles::bls12_381::*;
+    use precompiles::tests::{check_failures, check_vectors};
+
+    #[test]
+    fn g1() {
+        check_vectors(&G1Add, include_str!("../../tests/fixtures/precompiles/blsG1Add.json"));
+        check_failures(&G1Add, include_str!("../../tests/fixtures/precompiles/fail-blsG1Add.json"));
+        check_vectors(&G1Msm, include_str!("../../tests/fixtures/precompiles/blsG1MultiExp.json"));
+        check_failures(&G1Msm, include_str!("../../tests/fixtures/precompiles/fail-blsG1MultiExp.json"));
+        check_vectors(&MapFpToG1, include_str!("../../tests/fixtures/precompiles/blsMapG1.json"));
+        check_failures(&MapFpToG1, include_str!("../../tests/fixtures/precompiles/fail-blsMapG1.json"));
+    }
+
+    #[test]
+    fn g2() {
+        check_vectors(&G2Add, include_str!("../../tests/fixtures/precompiles/blsG2Add.json"));
+        check_failures(&G2Add, include_str!("../../tests/fixtures/precompiles/fail-blsG2Add.json"));
+        check_vectors(&G2Msm, include_str!("../../tests/fixtures/precompiles/blsG2MultiExp.json"));
+        check_failures(&G2Msm, include_str!("../../tests/fixtures/precompiles/fail-blsG2MultiExp.json"));
+        check_vectors(&MapFp2ToG2, include_str!("../../tests/fixtures/precompiles/blsMapG2.json"));
+        check_failures(&MapFp2ToG2, include_str!("../../tests/fixtures/precompiles/fail-blsMapG2.json"));
+    }
+
+    #[test]
+    fn pairing() {
+        check_vectors(&Pairing, include_str!("../../tests/fixtures/precompiles/blsPairing.json"));
+        check_failures(&Pairing, include_str!("../../tests/fixtures/precompiles/fail-blsPairing.json"));
+    }
+
+    #[test]
+    fn msm_discounts() {
+        // the discount stops growing after 128 pairs
+        assert_eq!(G1Msm.gas(&[0; 160]), U256::from(12_000));
+        assert_eq!(G1Msm.gas(&[0; 160 * 128]), U256::from(128u64 * 12_000 * 519 / 1000));
+        assert_eq!(G1Msm.gas(&[0; 160 * 200]), U256::from(200u64 * 12_000 * 519 / 1000));
+        assert_eq!(G2Msm.gas(&[0; 288 * 2]), U256::from(2 * 22_500));
+        assert_eq!(G2Msm.gas(&[0; 288 * 200]), U256::from(200u64 * 22_500 * 524 / 1000));
+
+        // a sum of nothing but points at infinity is the point at infinity
+        assert_eq!(G1Msm.execute(&[0; 320]), Some(vec![0; 128]));
+        assert_eq!(G2Msm.execute(&[0; 288]), Some(vec![0; 256]));
+    }
+}
@@ -0,0 +1,527 @@
+/// The world state, σ in the Yellow Paper: a mapping from addresses to account states. The VM
+/// only sees the current frame's `Env` directly; everything about other accounts goes through a
+/// `StateBackend`, so the state can come from wherever the embedder keeps it.
+use std::collections::{HashMap, HashSet};
+use bigint::uint::U256;
+use nibble_vec::NibbleVec;
+use trie::trie::{Rlp, Trie};
+use {AccountState, Address, K256, keccak, u256_to_addr};
+
+/// KEC(()), the hash of empty code
+pub fn empty_code_hash() -> K256 {
+    K256(keccak(&[]))
+}
+
+/// TRIE(∅), the root of an empty trie. This is KEC(RLP("")) = KEC(0x80).
+pub fn empty_trie_root() -> K256 {
+    K256(keccak(&[0x80]))
+}
+
+/// The root of an account's storage trie, which maps KEC(key) to RLP(value) for every non-zero
+/// value (section 4.1 of the Yellow Paper).
+pub fn storage_root(storage: &HashMap<U256, U256>) -> K256 {
+    let mut trie = Trie::new();
+    for (key, value) in storage.iter().filter(|&(_, value)| !value.is_zero()) {
+        let mut key_bytes = [0u8; 32];
+        key.to_big_endian(&mut key_bytes);
+        let mut hashed_key = [0u8; 32];
+        keccak(&key_bytes).to_big_endian(&mut hashed_key);
+
+        let mut value_bytes = [0u8; 32];
+        value.to_big_endian(&mut value_bytes);
+        let trimmed = value_bytes.iter().cloned().skip_while(|b| *b == 0).collect::<Vec<u8>>();
+
+        trie.insert(
+            NibbleVec::from_byte_vec(hashed_key.to_vec()),
+            NibbleVec::from_byte_vec(trimmed.rlp().to_vec()),
+        );
+    }
+    K256(trie.hash())
+}
+
+/// The address of a contract made by CREATE or a creation transaction: the low 160 bits of
+/// KEC(RLP((sender, nonce))), from section 7 of the Yellow Paper. `nonce` is the sender's nonce
+/// before the creation bumps it.
+pub fn create_address(sender: &Address, nonce: u64) -> Address {
+    let nonce_bytes = nonce.to_be_bytes().iter().cloned().skip_while(|b| *b == 0).collect::<Vec<u8>>();
+    let encoded = vec![sender.0.to_vec().rlp(), nonce_bytes.rlp()].rlp().to_vec();
+    u256_to_addr(keccak(&encoded))
+}
+
+/// The address of a contract made by CREATE2 (EIP-1014): the low 160 bits of
+/// KEC(0xff ++ sender ++ salt ++ KEC(init_code))
+pub fn create2_address(sender: &Address, salt: U256, init_code: &[u8]) -> Address {
+    let mut preimage = vec![0xff];
+    preimage.extend_from_slice(&sender.0);
+    let mut word = [0u8; 32];
+    salt.to_big_endian(&mut word);
+    preimage.extend_from_slice(&word);
+    keccak(init_code).to_big_endian(&mut word);
+    preimage.extend_from_slice(&word);
+    u256_to_addr(keccak(&preimage))
+}
+
+pub trait StateBackend {
+    /// Whether there's an account at `address` at all, empty or not
+    fn exists(&self, address: &Address) -> bool;
+
+    fn balance(&self, address: &Address) -> U256;
+
+    fn nonce(&self, address: &Address) -> u64;
+
+    /// The code of the account at `address`, empty if there's no account
+    fn code(&self, address: &Address) -> Vec<u8>;
+
+    /// KEC of the account's code, or zero if there's no account
+    fn code_hash(&self, address: &Address) -> K256;
+
+    /// The value at `key` in the account's storage, zero if it was never set
+    fn storage(&self, address: &Address, key: U256) -> U256;
+
+    fn set_storage(&mut self, address: &Address, key: U256, value: U256);
+
+    /// Set the account's balance, creating an empty account first if there isn't one
+    fn set_balance(&mut self, address: &Address, balance: U256);
+
+    /// Set the account's nonce, creating an empty account first if there isn't one
+    fn set_nonce(&mut self, address: &Address, nonce: u64);
+
+    /// Set the account's code (and so its code hash), creating an empty account first if there
+    /// isn't one
+    fn set_code(&mut self, address: &Address, code: Vec<u8>);
+
+    /// Remove the account at `address` along with its storage
+    fn remove_account(&mut self, address: &Address);
+
+    /// EMPTY(σ, a) from EIP-161: no code, zero nonce and zero balance
+    fn is_empty(&self, address: &Address) -> bool {
+        self.balance(address).is_zero()
+            && self.nonce(address) == 0
+            && self.code_hash(address) == empty_code_hash()
+    }
+
+    /// DEAD(σ, a) from EIP-161: there's no account or it's empty
+    fn is_dead(&self, address: &Address) -> bool {
+        !self.exists(address) || self.is_empty(address)
+    }
+
+    /// Called once a transaction has finished successfully. Backends that keep tries or caches
+    /// derived from the state can bring them up to date here.
+    fn commit(&mut self) {}
+}
+
+/// A change made to the world state during execution, remembering enough to undo it
+#[derive(Clone, Debug)]
+enum JournalEntry {
+    Storage { address: Address, key: U256, previous: U256 },
+    Balance { address: Address, previous: U256 },
+    Nonce { address: Address, previous: u64 },
+    Code { address: Address, previous: Vec<u8> },
+    /// An account that didn't exist before was created
+    AccountCreated { address: Address },
+    TransientStorage { address: Address, key: U256, previous: U256 },
+}
+
+/// Every change made to the world state during the current transaction, in order. Reverting to a
+/// checkpoint undoes all the changes made since it was taken.
+#[derive(Clone, Debug, Default)]
+pub struct Journal {
+    entries: Vec<JournalEntry>,
+    /// The value each written storage slot had when the transaction started
+    original_storage: HashMap<(Address, U256), U256>,
+    /// Transient storage (EIP-1153), which works like storage but only lasts as long as the
+    /// transaction, so it's kept here rather than in the world state
+    transient_storage: HashMap<(Address, U256), U256>,
+}
+
+impl Journal {
+    pub fn new() -> Journal {
+        Journal::default()
+    }
+
+    pub fn checkpoint(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The value of a storage slot at the start of the transaction (the "original value" of
+    /// EIP-2200)
+    pub fn original_storage(&self, world: &dyn StateBackend, address: &Address, key: U256) -> U256 {
+        match self.original_storage.get(&(address.clone(), key)) {
+            Some(value) => *value,
+            None => world.storage(address, key),
+        }
+    }
+
+    pub fn set_storage(&mut self, world: &mut dyn StateBackend, address: &Address, key: U256, value: U256) {
+        let previous = world.storage(address, key);
+        self.original_storage.entry((address.clone(), key)).or_insert(previous);
+        self.entries.push(JournalEntry::Storage { address: address.clone(), key, previous });
+        world.set_storage(address, key, value);
+    }
+
+    pub fn transient_storage(&self, address: &Address, key: U256) -> U256 {
+        self.transient_storage.get(&(address.clone(), key)).cloned().unwrap_or_else(U256::zero)
+    }
+
+    pub fn set_transient_storage(&mut self, address: &Address, key: U256, value: U256) {
+        let previous = self.transient_storage(address, key);
+        self.entries.push(JournalEntry::TransientStorage { address: address.clone(), key, previous });
+        self.transient_storage.insert((address.clone(), key), value);
+    }
+
+    /// Discard transient storage once the transaction has completed
+    pub fn clear_transient_storage(&mut self) {
+        self.transient_storage.clear();
+    }
+
+    /// Remember to remove the account at `address` on a revert if it's about to be created
+    fn note_creation(&mut self, world: &dyn StateBackend, address: &Address) {
+        if !world.exists(address) {
+            self.entries.push(JournalEntry::AccountCreated { address: address.clone() });
+        }
+    }
+
+    pub fn set_balance(&mut self, world: &mut dyn StateBackend, address: &Address, balance: U256) {
+        self.note_creation(world, address);
+        let previous = world.balance(address);
+        self.entries.push(JournalEntry::Balance { address: address.clone(), previous });
+        world.set_balance(address, balance);
+    }
+
+    pub fn set_nonce(&mut self, world: &mut dyn StateBackend, address: &Address, nonce: u64) {
+        self.note_creation(world, address);
+        let previous = world.nonce(address);
+        self.entries.push(JournalEntry::Nonce { address: address.clone(), previous });
+        world.set_nonce(address, nonce);
+    }
+
+    pub fn set_code(&mut self, world: &mut dyn StateBackend, address: &Address, code: Vec<u8>) {
+        self.note_creation(world, address);
+        let previous = world.code(address);
+        self.entries.push(JournalEntry::Code { address: address.clone(), previous });
+        world.set_code(address, code);
+    }
+
+    /// Move `value` Wei from `from` to `to`. The caller has already checked that `from` can
+    /// afford it.
+    pub fn transfer(&mut self, world: &mut dyn StateBackend, from: &Address, to: &Address, value: U256) {
+        let from_balance = world.balance(from);
+        self.set_balance(world, from, from_balance - value);
+        let to_balance = world.balance(to);
+        self.set_balance(world, to, to_balance.saturating_add(value));
+    }
+
+    /// Undo every change made since `checkpoint`, most recent first
+    pub fn revert_to(&mut self, checkpoint: usize, world: &mut dyn StateBackend) {
+        while self.entries.len() > checkpoint {
+            match self.entries.pop() {
+                Some(JournalEntry::Storage { address, key, previous }) =>
+                    world.set_storage(&address, key, previous),
+                Some(JournalEntry::Balance { address, previous }) =>
+                    world.set_balance(&address, previous),
+                Some(JournalEntry::Nonce { address, previous }) =>
+                    world.set_nonce(&address, previous),
+                Some(JournalEntry::Code { address, previous }) =>
+                    world.set_code(&address, previous),
+                Some(JournalEntry::AccountCreated { address }) =>
+                    world.remove_account(&address),
+                Some(JournalEntry::TransientStorage { address, key, previous }) => {
+                    self.transient_storage.insert((address, key), previous);
+                },
+                None => break,
+            }
+        }
+    }
+}
+
+/// A `StateBackend` that keeps everything in memory. Code is stored by its hash, like in a real
+/// client's database, and storage roots are recomputed on `commit`.
+#[derive(Clone, Debug, Default)]
+pub struct InMemoryState {
+    accounts: HashMap<Address, AccountState>,
+    codes:    HashMap<K256, Vec<u8>>,
+    storage:  HashMap<Address, HashMap<U256, U256>>,
+    /// accounts whose storage changed since the last commit
+    dirty:    HashSet<Address>,
+}
+
+impl InMemoryState {
+    pub fn new() -> InMemoryState {
+        InMemoryState::default()
+    }
+
+    /// Create or overwrite the account at `address`
+    pub fn insert_account(&mut self, address: Address, balance: U256, nonce: u64, code: Vec<u8>) {
+        let code_hash = K256(keccak(&code));
+        self.codes.insert(code_hash.clone(), code);
+        self.accounts.insert(address, AccountState {
+            nonce,
+            balance,
+            storage_root: empty_trie_root(),
+            code_hash,
+        });
+    }
+
+    pub fn insert_storage(&mut self, address: Address, key: U256, value: U256) {
+        self.set_storage(&address, key, value);
+    }
+
+    pub fn account(&self, address: &Address) -> Option<&AccountState> {
+        self.accounts.get(address)
+    }
+
+    /// The account at `address`, created empty if there isn't one
+    fn account_mut(&mut self, address: &Address) -> &mut AccountState {
+        if !self.accounts.contains_key(address) {
+            self.insert_account(address.clone(), U256::zero(), 0, Vec::new());
+        }
+        self.accounts.get_mut(address).expect("account was just inserted")
+    }
+}
+
+impl StateBackend for InMemoryState {
+    fn exists(&self, address: &Address) -> bool {
+        self.accounts.contains_key(address)
+    }
+
+    fn balance(&self, address: &Address) -> U256 {
+        self.accounts.get(address).map_or(U256::zero(), |account| account.balance)
+    }
+
+    fn nonce(&self, address: &Address) -> u64 {
+        self.accounts.get(address).map_or(0, |account| account.nonce)
+    }
+
+    fn code(&self, address: &Address) -> Vec<u8> {
+        self.accounts.get(address)
+            .and_then(|account| self.codes.get(&account.code_hash))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn code_hash(&self, address: &Address) -> K256 {
+        self.accounts.get(address).map_or(K256(U256::zero()), |account| account.code_hash.clone())
+    }
+
+    fn storage(&self, address: &Address, key: U256) -> U256 {
+        self.storage.get(address)
+            .and_then(|storage| storage.get(&key))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn set_storage(&mut self, address: &Address, key: U256, value: U256) {
+        let storage = self.storage.entry(address.clone()).or_default();
+        if value.is_zero() {
+            storage.remove(&key);
+        } else {
+            storage.insert(key, value);
+        }
+        self.dirty.insert(address.clone());
+    }
+
+    fn set_balance(&mut self, address: &Address, balance: U256) {
+        self.account_mut(address).balance = balance;
+    }
+
+    fn set_nonce(&mut self, address: &Address, nonce: u64) {
+        self.account_mut(address).nonce = nonce;
+    }
+
+    fn set_code(&mut self, address: &Address, code: Vec<u8>) {
+        let code_hash = K256(keccak(&code));
+        self.codes.insert(code_hash.clone(), code);
+        self.account_mut(address).code_hash = code_hash;
+    }
+
+    fn remove_account(&mut self, address: &Address) {
+        self.accounts.remove(address);
+        self.storage.remove(address);
+        self.dirty.remove(address);
+    }
+
+    fn commit(&mut self) {
+        for address in self.dirty.drain() {
+            if let Some(account) = self.accounts.get_mut(&address) {
+                account.storage_root = match self.storage.get(&address) {
+                    Some(storage) => storage_root(storage),
+                    None => empty_trie_root(),
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bigint::uint::U256;
+    use std::collections::HashMap;
+    use data_encoding::HEXLOWER;
+    use state::{InMemoryState, Journal, StateBackend, create_address, create2_address, empty_code_hash,
+                empty_trie_root, storage_root};
+    use {Address, K256};
+
+    fn hex_k256(hex: &str) -> K256 {
+        K256(U256::from(HEXLOWER.decode(hex.as_bytes()).unwrap().as_slice()))
+    }
+
+    fn hex_address(hex: &str) -> Address {
+        let mut address = [0u8; 20];
+        address.copy_from_slice(&HEXLOWER.decode(hex.as_bytes()).unwrap());
+        Address(address)
+    }
+
+    #[test]
+    fn in_memory_state() {
+        let alice = Address([0xaa; 20]);
+        let bob   = Address([0xbb; 20]);
+        let mut state = InMemoryState::new();
+        state.insert_account(alice.clone(), U256::from(100), 1, vec![0x60, 0x01]);
+        state.insert_storage(alice.clone(), U256::from(1), U256::from(2));
+
+        assert!(state.exists(&alice));
+        assert_eq!(state.balance(&alice), U256::from(100));
+        assert_eq!(state.nonce(&alice), 1);
+        assert_eq!(state.code(&alice), vec![0x60, 0x01]);
+        assert_eq!(state.storage(&alice, U256::from(1)), U256::from(2));
+        assert!(state.storage(&alice, U256::from(2)).is_zero());
+        assert!(!state.is_empty(&alice));
+
+        assert!(!state.exists(&bob));
+        assert!(state.balance(&bob).is_zero());
+        assert!(state.code(&bob).is_empty());
+        assert_eq!(state.code_hash(&bob), K256(U256::zero()));
+
+        state.insert_account(bob.clone(), U256::zero(), 0, vec![]);
+        assert_eq!(state.code_hash(&bob), empty_code_hash());
+        assert!(state.is_empty(&bob));
+    }
+
+    #[test]
+    fn storage_roots() {
+        assert_eq!(storage_root(&HashMap::new()), empty_trie_root());
+        assert_eq!(empty_trie_root(), hex_k256("56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421"));
+
+        let mut storage = HashMap::new();
+        storage.insert(U256::zero(), U256::from(42));
+        assert_eq!(storage_root(&storage), hex_k256("81d1fa699f807735499cf6f7df860797cf66f6a66b565cfcda3fae3521eb6861"));
+        // zero values aren't stored in the trie
+        storage.insert(U256::one(), U256::zero());
+        assert_eq!(storage_root(&storage), hex_k256("81d1fa699f807735499cf6f7df860797cf66f6a66b565cfcda3fae3521eb6861"));
+
+        let storage = (0..21).map(|i| (U256::from(i), U256::from(i + 1))).collect();
+        assert_eq!(storage_root(&storage), hex_k256("96ee83d8002eef6c506b5dcb8cee52575c1640eec8cf952d754da5287a469eb8"));
+
+        let mut storage = HashMap::new();
+        storage.insert(U256::one(), U256::one());
+        storage.insert(U256::max_value(), U256::max_value());
+        storage.insert(U256::from(1000), U256::from(255));
+        storage.insert(U256::from(256), U256::from(65536));
+        assert_eq!(storage_root(&storage), hex_k256("bd1f86610a5c59a35c3425ded65e26570cbe375991fd3907b536ac811500d237"));
+    }
+
+    #[test]
+    fn commit_updates_storage_root() {
+        let alice = Address([0xaa; 20]);
+        let mut state = InMemoryState::new();
+        state.insert_account(alice.clone(), U256::zero(), 0, vec![]);
+        state.set_storage(&alice, U256::zero(), U256::from(42));
+        assert_eq!(state.account(&alice).unwrap().storage_root, empty_trie_root());
+        state.commit();
+        assert_eq!(state.account(&alice).unwrap().storage_root, hex_k256("81d1fa699f807735499cf6f7df860797cf66f6a66b565cfcda3fae3521eb6861"));
+
+        state.set_storage(&alice, U256::zero(), U256::zero());
+        state.commit();
+        assert_eq!(state.account(&alice).unwrap().storage_root, empty_trie_root());
+    }
+
+    #[test]
+    fn journal() {
+        let alice = Address([0xaa; 20]);
+        let mut state = InMemoryState::new();
+        state.insert_storage(alice.clone(), U256::one(), U256::from(10));
+        let mut journal = Journal::new();
+
+        journal.set_storage(&mut state, &alice, U256::one(), U256::from(20));
+        let checkpoint = journal.checkpoint();
+        journal.set_storage(&mut state, &alice, U256::one(), U256::from(30));
+        journal.set_storage(&mut state, &alice, U256::from(2), U256::from(40));
+        assert_eq!(journal.original_storage(&state, &alice, U256::one()), U256::from(10));
+        assert_eq!(journal.original_storage(&state, &alice, U256::from(2)), U256::zero());
+
+        journal.revert_to(checkpoint, &mut state);
+        assert_eq!(state.storage(&alice, U256::one()), U256::from(20));
+        assert!(state.storage(&alice, U256::from(2)).is_zero());
+
+        journal.revert_to(0, &mut state);
+        assert_eq!(state.storage(&alice, U256::one()), U256::from(10));
+    }
+
+    #[test]
+    fn journal_transient_storage() {
+        let alice = Address([0xaa; 20]);
+        let mut state = InMemoryState::new();
+        let mut journal = Journal::new();
+
+        journal.set_transient_storage(&alice, U256::one(), U256::from(10));
+        let checkpoint = journal.checkpoint();
+        journal.set_transient_storage(&alice, U256::one(), U256::from(20));
+        assert_eq!(journal.transient_storage(&alice, U256::one()), U256::from(20));
+        // it's separate from storage
+        assert!(state.storage(&alice, U256::one()).is_zero());
+
+        journal.revert_to(checkpoint, &mut state);
+        assert_eq!(journal.transient_storage(&alice, U256::one()), U256::from(10));
+        journal.clear_transient_storage();
+        assert!(journal.transient_storage(&alice, U256::one()).is_zero());
+    }
+
+    #[test]
+    fn creation_addresses() {
+        let sender = hex_address("6ac7ea33f8831ea9dcc53393aaa88b25a785dbf0");
+        assert_eq!(create_address(&sender, 0), hex_address("cd234a471b72ba2f1ccf0a70fcaba648a5eecd8d"));
+        assert_eq!(create_address(&sender, 1), hex_address("343c43a37d37dff08ae8c4a11544c718abb4fcf8"));
+        assert_eq!(create_address(&sender, 300), hex_address("47bbbb5fe97aa84c3fa30dafbb067284d250c24c"));
+
+        // the examples from EIP-1014
+        let zero = Address([0; 20]);
+        assert_eq!(create2_address(&zero, U256::zero(), &[0]), hex_address("4d1a2e2bb4f88f0250f26ffff098b0b30b26bf38"));
+        assert_eq!(create2_address(&zero, U256::zero(), &[]), hex_address("e33c0c7f7df4809055c3eba6c09cfe4baf1bd9e0"));
+        let sender = hex_address("00000000000000000000000000000000deadbeef");
+        assert_eq!(create2_address(&sender, U256::from(0xcafebabeu64), &[0xde, 0xad, 0xbe, 0xef]),
+                   hex_address("60f3f640a8508fc6a86d45df051962668e1e8ac7"));
+    }
+
+    #[test]
+    fn journal_creations() {
+        let alice = Address([0xaa; 20]);
+        let mut state = InMemoryState::new();
+        let mut journal = Journal::new();
+
+        journal.set_nonce(&mut state, &alice, 1);
+        journal.set_code(&mut state, &alice, vec![0x60, 0x01]);
+        assert_eq!(state.nonce(&alice), 1);
+        assert_eq!(state.code_hash(&alice), K256(::keccak(&[0x60, 0x01])));
+
+        journal.revert_to(0, &mut state);
+        assert!(!state.exists(&alice));
+    }
+
+    #[test]
+    fn journal_transfers() {
+        let alice = Address([0xaa; 20]);
+        let bob   = Address([0xbb; 20]);
+        let mut state = InMemoryState::new();
+        state.insert_account(alice.clone(), U256::from(100), 0, vec![]);
+        let mut journal = Journal::new();
+
+        journal.transfer(&mut state, &alice, &bob, U256::from(30));
+        assert_eq!(state.balance(&alice), U256::from(70));
+        assert_eq!(state.balance(&bob), U256::from(30));
+        assert!(state.exists(&bob));
+
+        journal.revert_to(0, &mut state);
+        assert_eq!(state.balance(&alice), U256::from(100));
+        assert!(!state.exists(&bob));
+    }
+}
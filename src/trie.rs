@@ -175,12 +175,16 @@ pub mod trie {
                         subtree.update(new_extra, value);
                         result = Some(maybe_extend(prefix, *subtree));
                     } else {
-                        // the new key we're inserting bottoms out here
-                        result = Some(two_branch(
-                            prefix,
-                            old_extra, nibbles.clone(),
-                            new_extra, value
-                        ));
+                        // the keys diverge partway through the extension, so split it with a
+                        // branch: the old subtree hangs off one child (behind whatever is left
+                        // of the extension), and the new value goes in another
+                        let (hd, tl) = nibble_head_tail(old_extra);
+                        let old_child = maybe_extend(tl, (**subtree).clone());
+                        let mut children = no_children![];
+                        children[hd as usize] = Some(Box::new(old_child));
+                        let mut branch = Branch { children, data: None };
+                        branch.update(new_extra, value);
+                        result = Some(maybe_extend(prefix, branch));
                     }
                 },
 
@@ -219,16 +223,17 @@ pub mod trie {
         }
     }
 
+    // n(J, i) from appendix D: nodes whose RLP is shorter than 32 bytes are embedded directly,
+    // anything larger is referred to by its hash
     fn rlp_reference(node: &TrieNode) -> RlpEncoded {
-        match node {
-            &Leaf{..}      => node.rlp(),
-            &Extension{..} => node.rlp(),
-            &Branch{..}    => {
-                let mut hasher = Keccak256::default();
-                hasher.input(node.deref().rlp().to_vec().as_slice());
-                let subtree_hash: &[u8] = &hasher.result();
-                Vec::from(subtree_hash).rlp()
-            }
+        let encoded = node.rlp().to_vec();
+        if encoded.len() < 32 {
+            RlpEncoded(encoded)
+        } else {
+            let mut hasher = Keccak256::default();
+            hasher.input(encoded.as_slice());
+            let subtree_hash: &[u8] = &hasher.result();
+            Vec::from(subtree_hash).rlp()
         }
     }
 
@@ -424,7 +429,12 @@ pub mod trie {
 
         pub fn hash(&self) -> U256 {
             let mut hasher = Keccak256::default();
-            hasher.input(self.rlp_node().to_vec().as_slice());
+            if self.is_empty {
+                // the root of an empty trie is KEC(RLP(""))
+                hasher.input(Vec::<u8>::new().rlp().to_vec().as_slice());
+            } else {
+                hasher.input(self.rlp_node().to_vec().as_slice());
+            }
             let out: &[u8] = &hasher.result();
             U256::from(out)
         }
@@ -0,0 +1,82 @@
+/// Modular arithmetic on words whose intermediate results don't fit in 256 bits. ADDMOD and
+/// MULMOD take the sum or product modulo N without first reducing it modulo 2^256, so it's
+/// computed as a `U512` and only the remainder, which is smaller than N, is brought back to a
+/// word.
+use bigint::uint::{U256, U512};
+
+/// (a + b) mod n, or zero if n is zero
+pub fn addmod(a: U256, b: U256, n: U256) -> U256 {
+    if n.is_zero() {
+        return U256::zero();
+    }
+    ((U512::from(a) + U512::from(b)) % U512::from(n)).into()
+}
+
+/// (a * b) mod n, or zero if n is zero
+pub fn mulmod(a: U256, b: U256, n: U256) -> U256 {
+    if n.is_zero() {
+        return U256::zero();
+    }
+    (a.full_mul(b) % U512::from(n)).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use bigint::uint::U256;
+    use num::{BigUint, Zero};
+    use proptest::prelude::*;
+    use wide::{addmod, mulmod};
+
+    fn to_big(word: U256) -> BigUint {
+        let mut bytes = [0; 32];
+        word.to_big_endian(&mut bytes);
+        BigUint::from_bytes_be(&bytes)
+    }
+
+    fn from_big(big: BigUint) -> U256 {
+        U256::from_big_endian(&big.to_bytes_be())
+    }
+
+    /// Words weighted towards the edges, where wrapping would show up
+    fn word() -> impl Strategy<Value = U256> {
+        prop_oneof![
+            any::<[u8; 32]>().prop_map(|bytes| U256::from_big_endian(&bytes)),
+            any::<u64>().prop_map(U256::from),
+            any::<u64>().prop_map(|n| !U256::zero() - U256::from(n)),
+        ]
+    }
+
+    #[test]
+    fn edges() {
+        let max = !U256::zero();
+        assert_eq!(addmod(max, U256::one(), U256::from(2)), U256::zero());
+        assert_eq!(addmod(max, max, max), U256::zero());
+        assert_eq!(addmod(max, U256::from(2), max), U256::from(2));
+        assert_eq!(mulmod(max, max, U256::from(12)), U256::from(9));
+        assert_eq!(mulmod(max, max, max - U256::one()), U256::one());
+        assert_eq!(addmod(U256::one(), U256::one(), U256::zero()), U256::zero());
+        assert_eq!(mulmod(max, max, U256::zero()), U256::zero());
+    }
+
+    proptest! {
+        #[test]
+        fn addmod_matches_biguint(a in word(), b in word(), n in word()) {
+            let expected = if n.is_zero() {
+                BigUint::zero()
+            } else {
+                (to_big(a) + to_big(b)) % to_big(n)
+            };
+            prop_assert_eq!(addmod(a, b, n), from_big(expected));
+        }
+
+        #[test]
+        fn mulmod_matches_biguint(a in word(), b in word(), n in word()) {
+            let expected = if n.is_zero() {
+                BigUint::zero()
+            } else {
+                (to_big(a) * to_big(b)) % to_big(n)
+            };
+            prop_assert_eq!(mulmod(a, b, n), from_big(expected));
+        }
+    }
+}